@@ -6,6 +6,8 @@ use heapless::HistoryBuffer;
 use icd::{ButtonAction, ButtonActionPerformed};
 use postcard_rpc::server::Sender;
 
+pub(crate) const BUTTON_COUNT: u8 = 4;
+
 struct PhysicalButtonInputs {
     fn_1: Input<'static>,
     fn_2: Input<'static>,
@@ -127,6 +129,11 @@ pub async fn run(r: ButtonResources, rpc_sender: Sender<AppTx>) {
             info!("Button action: {}", action);
 
             if let Some(action) = action {
+                if crate::display::notify_activity() {
+                    info!("Woke from screensaver, not forwarding button action");
+                    continue;
+                }
+
                 if rpc_sender
                     .publish::<ButtonActionPerformed>(seq.into(), &action)
                     .await
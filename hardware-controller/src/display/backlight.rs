@@ -0,0 +1,39 @@
+use core::time::Duration;
+use embassy_rp::{
+    bind_interrupts,
+    peripherals::PIO0,
+    pio::{InterruptHandler, Pio},
+    pio_programs::pwm::{PioPwm, PioPwmProgram},
+};
+use peek_o_display_bsp::peripherals::DISPLAY_BACKLIGHT;
+
+bind_interrupts!(struct Irqs {
+    PIO0_IRQ_0 => InterruptHandler<PIO0>;
+});
+
+/// Display backlight, dimmed using a PIO generated PWM signal.
+pub(crate) struct Backlight {
+    pwm: PioPwm<'static, PIO0, 0>,
+}
+
+impl Backlight {
+    const PERIOD: Duration = Duration::from_millis(1);
+
+    pub(crate) fn new(pio: PIO0, pin: DISPLAY_BACKLIGHT) -> Self {
+        let Pio {
+            mut common, sm0, ..
+        } = Pio::new(pio, Irqs);
+
+        let program = PioPwmProgram::new(&mut common);
+        let mut pwm = PioPwm::new(&mut common, sm0, pin, &program);
+        pwm.set_period(Self::PERIOD);
+        pwm.start();
+
+        Self { pwm }
+    }
+
+    pub(crate) fn set_brightness(&mut self, brightness: u8) {
+        self.pwm
+            .write(Self::PERIOD * brightness.into() / u8::MAX.into());
+    }
+}
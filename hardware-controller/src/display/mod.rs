@@ -1,25 +1,73 @@
-mod screens;
+mod backlight;
 
+use self::backlight::Backlight;
 use crate::{BoardSpi, DisplayResources};
 use defmt::{info, warn};
 use embassy_embedded_hal::shared_bus::blocking::spi::SpiDeviceWithConfig;
+use embassy_futures::select::{select3, select4, Either3, Either4};
 use embassy_rp::gpio::{Level, Output};
 use embassy_sync::{
     blocking_mutex::raw::CriticalSectionRawMutex,
+    signal::Signal,
     watch::{Sender, Watch},
 };
-use embassy_time::Delay;
-use embedded_graphics::Drawable;
-use icd::Screen;
+use embassy_time::{Delay, Duration, Instant, Timer};
+use embedded_graphics::{pixelcolor::Rgb666, prelude::DrawTarget, Drawable};
+use icd::{CharacterSelectScreen, Screen, ScreenKind, ScreenReport, ScreensaverSettings};
 use mipidsi::{
     interface::SpiInterface,
     models::ILI9341Rgb666,
     options::{ColorOrder, Orientation, Rotation},
 };
+use portable_atomic::{AtomicBool, Ordering};
+
+pub(crate) use screens::{HEIGHT, WIDTH};
+
+pub(crate) const SUPPORTED_SCREENS: [ScreenKind; 4] = [
+    ScreenKind::CharacterSelect,
+    ScreenKind::Choices,
+    ScreenKind::Reply,
+    ScreenKind::Attention,
+];
+
+const DEFAULT_BRIGHTNESS: u8 = u8::MAX;
+
+const ANIMATION_INTERVAL: Duration = Duration::from_millis(1500);
+
+const DEFAULT_SCREENSAVER: ScreensaverSettings = ScreensaverSettings {
+    timeout_secs: 300,
+    cycle_secs: 10,
+    brightness: 32,
+};
 
 pub(crate) type UpdateScreenSender = Sender<'static, CriticalSectionRawMutex, Screen, 1>;
 pub(crate) static UPDATE_SCREEN: Watch<CriticalSectionRawMutex, Screen, 1> = Watch::new();
 
+pub(crate) type UpdateBrightnessSender = Sender<'static, CriticalSectionRawMutex, u8, 1>;
+pub(crate) static UPDATE_BRIGHTNESS: Watch<CriticalSectionRawMutex, u8, 1> = Watch::new();
+
+pub(crate) type UpdateScreensaverSender =
+    Sender<'static, CriticalSectionRawMutex, ScreensaverSettings, 1>;
+pub(crate) static UPDATE_SCREENSAVER: Watch<CriticalSectionRawMutex, ScreensaverSettings, 1> =
+    Watch::new();
+
+static ACTIVITY: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static SCREENSAVER_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Records a button press.
+///
+/// Returns true if the screensaver was being shown, in which case the press was only used to wake
+/// the display and should not be acted upon.
+pub(crate) fn notify_activity() -> bool {
+    ACTIVITY.signal(());
+    SCREENSAVER_ACTIVE.swap(false, Ordering::Relaxed)
+}
+
+/// Describes how the content of a screen will fit on the display.
+pub(crate) fn report(screen: &Screen) -> ScreenReport {
+    screens::report(screen, screens::screen_box())
+}
+
 #[embassy_executor::task]
 pub async fn run(spi: BoardSpi, r: DisplayResources) {
     let mut config = embassy_rp::spi::Config::default();
@@ -30,13 +78,16 @@ pub async fn run(spi: BoardSpi, r: DisplayResources) {
 
     let dc = Output::new(r.dc, Level::Low);
     let rst = Output::new(r.reset, Level::Low);
-    let _backlight = Output::new(r.backlight, Level::High);
+
+    let mut brightness = DEFAULT_BRIGHTNESS;
+    let mut backlight = Backlight::new(r.pio, r.backlight);
+    backlight.set_brightness(brightness);
 
     let mut buffer = [0_u8; 512];
     let interface = SpiInterface::new(spi, dc, &mut buffer);
 
     let mut display = mipidsi::Builder::new(ILI9341Rgb666, interface)
-        .display_size(240, 320)
+        .display_size(HEIGHT, WIDTH)
         .orientation(
             Orientation::default()
                 .rotate(Rotation::Deg270)
@@ -47,30 +98,159 @@ pub async fn run(spi: BoardSpi, r: DisplayResources) {
         .init(&mut Delay)
         .expect("display should be initialised");
 
-    {
-        let screen = self::screens::SplashScreen {};
-        if screen.draw(&mut display).is_err() {
-            warn!("Failed to draw splash screen");
-        }
-    }
+    draw_screen(&mut display, None);
 
     let mut screen_rx = UPDATE_SCREEN
         .receiver()
         .expect("should have a receiver for the update screen watch");
 
-    loop {
-        let new_screen = screen_rx.changed().await;
+    let mut brightness_rx = UPDATE_BRIGHTNESS
+        .receiver()
+        .expect("should have a receiver for the update brightness watch");
 
-        info!("Drawing screen: {:?}", new_screen);
-        if match new_screen {
-            Screen::CharacterSelect(s) => {
-                self::screens::CharacterSelectScreen::new(s).draw(&mut display)
+    let mut screensaver_rx = UPDATE_SCREENSAVER
+        .receiver()
+        .expect("should have a receiver for the update screensaver watch");
+
+    let mut screensaver = DEFAULT_SCREENSAVER;
+    let mut current_screen: Option<Screen> = None;
+    let mut last_character_select: Option<CharacterSelectScreen> = None;
+
+    // The current screen, if it has content that changes over time
+    let mut animated: Option<screens::AnimatedScreen> = None;
+
+    // Index of the card currently shown by the screensaver, `None` when the screensaver is not active
+    let mut screensaver_card: Option<usize> = None;
+    let mut last_activity = Instant::now();
+
+    loop {
+        let timeout = match screensaver_card {
+            Some(_) => Timer::after(Duration::from_secs(screensaver.cycle_secs.into())),
+            // Anything needing attention stays on screen until it has been dealt with
+            None if screensaver.timeout_secs == 0
+                || matches!(current_screen, Some(Screen::Attention(_))) =>
+            {
+                Timer::at(Instant::MAX)
             }
-            Screen::Choices(s) => self::screens::ChoiceScreen::new(s).draw(&mut display),
-        }
-        .is_err()
+            None => Timer::at(last_activity + Duration::from_secs(screensaver.timeout_secs.into())),
+        };
+
+        let animation = match (&animated, screensaver_card) {
+            (Some(_), None) => Timer::after(ANIMATION_INTERVAL),
+            _ => Timer::at(Instant::MAX),
+        };
+
+        match select4(
+            screen_rx.changed(),
+            brightness_rx.changed(),
+            screensaver_rx.changed(),
+            select3(ACTIVITY.wait(), timeout, animation),
+        )
+        .await
         {
-            warn!("Failed to draw screen");
+            Either4::First(new_screen) => {
+                if let Screen::CharacterSelect(s) = &new_screen {
+                    last_character_select = Some(s.clone());
+                }
+                animated = screens::AnimatedScreen::new(&new_screen, screens::screen_box());
+
+                // Only drawn straight away if awake, otherwise it is shown when woken
+                if screensaver_card.is_none() {
+                    draw_screen(&mut display, Some(&new_screen));
+                }
+                current_screen = Some(new_screen);
+            }
+            Either4::Second(new_brightness) => {
+                info!("Setting brightness: {}", new_brightness);
+                brightness = new_brightness;
+                if screensaver_card.is_none() {
+                    backlight.set_brightness(brightness);
+                }
+            }
+            Either4::Third(new_screensaver) => {
+                info!("Setting screensaver: {:?}", new_screensaver);
+                screensaver = ScreensaverSettings {
+                    // A zero length cycle would redraw the screensaver as fast as possible
+                    cycle_secs: new_screensaver.cycle_secs.max(1),
+                    ..new_screensaver
+                };
+                last_activity = Instant::now();
+            }
+            Either4::Fourth(Either3::First(())) => {
+                last_activity = Instant::now();
+
+                if screensaver_card.take().is_some() {
+                    info!("Waking from screensaver");
+                    backlight.set_brightness(brightness);
+                    draw_screen(&mut display, current_screen.as_ref());
+                    animated = current_screen
+                        .as_ref()
+                        .and_then(|s| screens::AnimatedScreen::new(s, screens::screen_box()));
+                }
+            }
+            Either4::Fourth(Either3::Third(())) => {
+                if let Some(screen) = animated.as_mut() {
+                    screen.step();
+                    if screen.draw(&mut display).is_err() {
+                        warn!("Failed to animate screen");
+                    }
+                }
+            }
+            Either4::Fourth(Either3::Second(())) => {
+                let card = match screensaver_card {
+                    Some(card) => card.wrapping_add(1),
+                    None => {
+                        info!("Starting screensaver");
+                        SCREENSAVER_ACTIVE.store(true, Ordering::Relaxed);
+                        backlight.set_brightness(screensaver.brightness);
+                        0
+                    }
+                };
+
+                draw_screensaver_card(&mut display, last_character_select.as_ref(), card);
+                screensaver_card = Some(card);
+            }
         }
     }
 }
+
+fn draw_screen<D>(display: &mut D, screen: Option<&Screen>)
+where
+    D: DrawTarget<Color = Rgb666>,
+{
+    info!("Drawing screen: {:?}", screen);
+    if match screen {
+        None => screens::SplashScreen {}.draw(display),
+        Some(screen) => screens::draw_screen(screen, display),
+    }
+    .is_err()
+    {
+        warn!("Failed to draw screen");
+    }
+}
+
+/// Draws one card of the screensaver, alternating between the logo and the characters that were
+/// last shown on the character select screen.
+fn draw_screensaver_card<D>(
+    display: &mut D,
+    characters: Option<&CharacterSelectScreen>,
+    card: usize,
+) where
+    D: DrawTarget<Color = Rgb666>,
+{
+    let character = characters.and_then(|c| match card % 4 {
+        1 => Some(&c.prev),
+        2 => Some(&c.selected),
+        3 => Some(&c.next),
+        _ => None,
+    });
+
+    if match character {
+        Some(character) => screens::CharacterCardScreen::new(character.clone()).draw(display),
+        None => screens::SplashScreen {}.draw(display),
+    }
+    .is_err()
+    {
+        warn!("Failed to draw screensaver");
+    }
+}
@@ -26,6 +26,7 @@ assign_resources! {
         dc: DISPLAY_DC,
         reset: DISPLAY_RESET,
         backlight: DISPLAY_BACKLIGHT,
+        pio: PIO0,
     }
     buttons: ButtonResources {
         fn_1: PIN_16,
@@ -35,6 +36,7 @@ assign_resources! {
     }
     rpc: RpcResources {
         usb: USB,
+        flash: FLASH,
     }
     led: LedResources {
         led: PIN_25,
@@ -1,12 +1,24 @@
 use crate::{
-    display::{UpdateScreenSender, UPDATE_SCREEN},
+    buttons::BUTTON_COUNT,
+    display::{
+        UpdateBrightnessSender, UpdateScreenSender, UpdateScreensaverSender, HEIGHT,
+        SUPPORTED_SCREENS, UPDATE_BRIGHTNESS, UPDATE_SCREEN, UPDATE_SCREENSAVER, WIDTH,
+    },
     RpcResources,
 };
+use core::fmt::Write;
 use embassy_executor::Spawner;
-use embassy_rp::{bind_interrupts, peripherals::USB};
+use embassy_rp::{
+    bind_interrupts,
+    flash::{Blocking, Flash},
+    peripherals::{FLASH, USB},
+};
 use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
 use embassy_usb::UsbDevice;
-use icd::{Screen, SetDisplay, ENDPOINT_LIST, TOPICS_IN_LIST, TOPICS_OUT_LIST};
+use icd::{
+    DeviceInfo, GetDeviceInfo, Screen, ScreenReport, ScreensaverSettings, SetBrightness,
+    SetDisplay, SetScreensaver, ENDPOINT_LIST, SCHEMA_HASH, TOPICS_IN_LIST, TOPICS_OUT_LIST,
+};
 use postcard_rpc::{
     define_dispatch,
     header::VarHeader,
@@ -18,15 +30,18 @@ use postcard_rpc::{
         Dispatch, Sender, Server,
     },
 };
-use static_cell::ConstStaticCell;
+use static_cell::{ConstStaticCell, StaticCell};
 
 struct Context {
+    serial_number: &'static str,
     screen_tx: UpdateScreenSender,
+    brightness_tx: UpdateBrightnessSender,
+    screensaver_tx: UpdateScreensaverSender,
 }
 
 type AppDriver = embassy_rp::usb::Driver<'static, USB>;
 type AppStorage = WireStorage<ThreadModeRawMutex, AppDriver, 256, 256, 64, 256>;
-type BufStorage = PacketBuffers<1024, 1024>;
+type BufStorage = PacketBuffers<2048, 1024>;
 pub(crate) type AppTx = WireTxImpl<ThreadModeRawMutex, AppDriver>;
 type AppRx = WireRxImpl<AppDriver>;
 type AppServer = Server<AppTx, AppRx, WireRxBuf, MyApp>;
@@ -38,11 +53,33 @@ bind_interrupts!(pub struct Irqs {
 static PBUFS: ConstStaticCell<BufStorage> = ConstStaticCell::new(BufStorage::new());
 static STORAGE: AppStorage = AppStorage::new();
 
-fn usb_config() -> embassy_usb::Config<'static> {
+static SERIAL_NUMBER: StaticCell<heapless::String<16>> = StaticCell::new();
+
+const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+/// USB serial number, used by the host to tell controllers apart when several are attached.
+///
+/// This is the unique ID of the controller's flash chip, in hex.
+fn serial_number(flash: FLASH) -> &'static str {
+    let mut id = [0; 8];
+    Flash::<_, Blocking, FLASH_SIZE>::new_blocking(flash)
+        .blocking_unique_id(&mut id)
+        .expect("flash unique ID should be readable");
+
+    let mut serial = heapless::String::new();
+    for byte in id {
+        write!(serial, "{byte:02X}").expect("serial number should fit");
+    }
+
+    let serial: &'static heapless::String<16> = SERIAL_NUMBER.init(serial);
+    serial.as_str()
+}
+
+fn usb_config(serial_number: &'static str) -> embassy_usb::Config<'static> {
     let mut config = embassy_usb::Config::new(0x1209, 0x0001);
     config.manufacturer = Some("Dan Nixon");
     config.product = Some("llm-vn-controller");
-    config.serial_number = Some("0");
+    config.serial_number = Some(serial_number);
 
     // Required for windows compatibility.
     // https://developer.nordicsemi.com/nRF_Connect_SDK/doc/1.9.1/kconfig/CONFIG_CDC_ACM_IAD.html#help
@@ -64,9 +101,12 @@ define_dispatch! {
     endpoints: {
         list: ENDPOINT_LIST;
 
-        | EndpointTy | kind  | handler             |
-        | ---------- | ----- | ------------------- |
-        | SetDisplay | async | set_display_handler |
+        | EndpointTy     | kind     | handler                 |
+        | ----------     | -----    | ----------------------- |
+        | GetDeviceInfo  | blocking | device_info_handler     |
+        | SetDisplay     | async    | set_display_handler     |
+        | SetBrightness  | async    | set_brightness_handler  |
+        | SetScreensaver | async    | set_screensaver_handler |
     };
     topics_in: {
         list: TOPICS_IN_LIST;
@@ -79,17 +119,57 @@ define_dispatch! {
     };
 }
 
-async fn set_display_handler(context: &mut Context, _header: VarHeader, request: Screen) {
+fn device_info_handler(context: &mut Context, _header: VarHeader, _request: ()) -> DeviceInfo {
+    DeviceInfo {
+        firmware_version: env!("CARGO_PKG_VERSION")
+            .try_into()
+            .expect("firmware version should fit"),
+        git_hash: env!("GIT_HASH").try_into().expect("git hash should fit"),
+        serial_number: context
+            .serial_number
+            .try_into()
+            .expect("serial number should fit"),
+        schema_hash: SCHEMA_HASH,
+        display_width: WIDTH,
+        display_height: HEIGHT,
+        button_count: BUTTON_COUNT,
+        screens: SUPPORTED_SCREENS.into_iter().collect(),
+    }
+}
+
+async fn set_display_handler(
+    context: &mut Context,
+    _header: VarHeader,
+    request: Screen,
+) -> ScreenReport {
+    let report = crate::display::report(&request);
     context.screen_tx.send(request);
+    report
+}
+
+async fn set_brightness_handler(context: &mut Context, _header: VarHeader, request: u8) {
+    context.brightness_tx.send(request);
+}
+
+async fn set_screensaver_handler(
+    context: &mut Context,
+    _header: VarHeader,
+    request: ScreensaverSettings,
+) {
+    context.screensaver_tx.send(request);
 }
 
 pub fn init(r: RpcResources, spawner: Spawner) -> Sender<AppTx> {
     let driver = embassy_rp::usb::Driver::new(r.usb, Irqs);
     let pbufs = PBUFS.take();
-    let config = usb_config();
+    let serial_number = serial_number(r.flash);
+    let config = usb_config(serial_number);
 
     let context = Context {
+        serial_number,
         screen_tx: UPDATE_SCREEN.sender(),
+        brightness_tx: UPDATE_BRIGHTNESS.sender(),
+        screensaver_tx: UPDATE_SCREENSAVER.sender(),
     };
 
     let (device, tx_impl, rx_impl) = STORAGE.init(driver, config, pbufs.tx_buf.as_mut_slice());
@@ -1,38 +1,84 @@
-use crate::conversation::VnOutput;
+use crate::{
+    conversation::VnOutput, history::HistoryLimits, template::CharacterStyle, text::screen_text,
+    validation::ReplyLimits,
+};
 use embedded_graphics::pixelcolor::{Rgb666, Rgb888};
-use icd::{CharacterDetails, ChoiceScreen};
-use log::debug;
+use icd::{CharacterDetails, CharacterSelectScreen, ChoiceScreen, ReplyScreen, MAX_CHOICES};
+use log::{debug, info, warn};
+use ollama_rs::{
+    generation::{
+        chat::{request::ChatMessageRequest, ChatMessage},
+        parameters::KeepAlive,
+    },
+    models::ModelOptions,
+};
 use rand::seq::IndexedRandom;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct CharacterCollection {
     pub characters: Vec<Character>,
+
+    /// Characters that are defined but not shown to visitors
+    #[serde(skip)]
+    pub disabled: Vec<Character>,
 }
 
 impl CharacterCollection {
     pub(crate) fn load(s: &Path) -> Self {
         let content = std::fs::read_to_string(s).expect("Failed to read character file");
-        let characters: Self = toml::from_str(&content).expect("Failed to parse character file");
+        let mut characters: Self =
+            toml::from_str(&content).expect("Failed to parse character file");
         debug!("Loaded characters: {characters:#?}");
 
+        for character in &characters.characters {
+            character.validate();
+        }
+
+        let (disabled, enabled) = characters.characters.into_iter().partition(|c| c.disabled);
+        characters.characters = enabled;
+        characters.disabled = disabled;
+
         assert!(
             characters.characters.len() >= 3,
-            "There must be at least three characters defined."
+            "There must be at least three characters enabled."
         );
 
         characters
     }
 
+    /// Sets how long Ollama keeps each character's model loaded after a request.
+    pub(crate) fn set_keep_alive(&mut self, keep_alive: KeepAlive) {
+        for character in &mut self.characters {
+            character.keep_alive = Some(keep_alive.clone());
+        }
+    }
+
+    /// Index of the nearest available character before (`step` of -1) or after (`step` of 1) the
+    /// one at `idx`, or `idx` itself if no other character is available.
+    pub(crate) fn step(&self, idx: usize, step: isize) -> usize {
+        let len = self.characters.len();
+        (1..len)
+            .map(|i| (idx as isize + step * i as isize).rem_euclid(len as isize) as usize)
+            .find(|&i| self.characters[i].is_available())
+            .unwrap_or(idx)
+    }
+
+    /// Index of the first available character, if any are.
+    pub(crate) fn first_available(&self) -> Option<usize> {
+        self.characters.iter().position(Character::is_available)
+    }
+
     pub(crate) fn pick_subset(&self, idx: usize) -> [&Character; 3] {
-        let indices = if idx == 0 {
-            [self.characters.len() - 1, 0, 1]
-        } else if idx == self.characters.len() - 1 {
-            [idx - 1, idx, 0]
-        } else {
-            [idx - 1, idx, idx + 1]
-        };
+        let indices = [self.step(idx, -1), idx, self.step(idx, 1)];
 
         [
             &self.characters[indices[0]],
@@ -40,6 +86,15 @@ impl CharacterCollection {
             &self.characters[indices[2]],
         ]
     }
+
+    pub(crate) fn select_screen(&self, idx: usize) -> CharacterSelectScreen {
+        let charas = self.pick_subset(idx);
+        CharacterSelectScreen {
+            prev: charas[0].clone().into(),
+            selected: charas[1].clone().into(),
+            next: charas[2].clone().into(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -60,16 +115,208 @@ pub(crate) struct Character {
     pub name: String,
     pub description: String,
 
-    pub model_name: String,
+    /// Leave the character out, without removing it from the file
+    #[serde(default)]
+    pub disabled: bool,
+
+    /// Ollama model created for the character from its Modelfile
+    #[serde(default)]
+    pub model_name: Option<String>,
+
+    /// Ollama model to use as is, instead of `model_name`, configured by the fields below
+    #[serde(default)]
+    pub base_model: Option<String>,
+
+    /// Model used while the character's own model is not working, best used with `system_prompt`
+    /// as it does not have the character's Modelfile
+    #[serde(default)]
+    pub fallback_model: Option<String>,
+
+    /// Replaces the model's own system prompt
+    #[serde(default)]
+    pub system_prompt: Option<String>,
+
+    /// Overrides the model's own parameters
+    #[serde(default)]
+    pub options: GenerationOptions,
 
     text_colour: Colour,
     background_colour: Colour,
     border_colour: Colour,
 
     opening_lines: Vec<String>,
+
+    /// Show the character's replies on the controller screen as well as printing them
+    #[serde(default)]
+    pub show_reply: bool,
+
+    /// Remember visitors between conversations, by a visitor ID printed on their receipt
+    #[serde(default)]
+    pub remember_visitors: bool,
+
+    /// How much chat history is sent to the model
+    #[serde(default)]
+    pub history: HistoryLimits,
+
+    /// How long the character's replies can be, and how often it is asked to fix them
+    #[serde(default)]
+    pub limits: ReplyLimits,
+
+    /// End the conversation after this many replies from the character
+    #[serde(default)]
+    pub max_turns: Option<usize>,
+
+    /// How the character's receipts differ from the receipt template
+    #[serde(default)]
+    pub receipt: CharacterStyle,
+
+    /// How long Ollama keeps the model loaded after each request, its default if not set
+    #[serde(skip)]
+    keep_alive: Option<KeepAlive>,
+
+    /// Whether the character's models are working, shared between clones of the character
+    #[serde(skip)]
+    availability: Arc<AtomicU8>,
+
+    /// When a request was last made to the character's model, shared between clones of the
+    /// character
+    #[serde(skip)]
+    last_used: Arc<Mutex<Option<Instant>>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Availability {
+    Available,
+    /// The character's own model is not working, its fallback model is used instead
+    Fallback,
+    Unavailable,
+}
+
+/// Model parameters, unset ones are left to the model's defaults.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct GenerationOptions {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    /// Size of the context window, in tokens
+    pub num_ctx: Option<u64>,
+    pub seed: Option<i32>,
+}
+
+impl From<&GenerationOptions> for ModelOptions {
+    fn from(options: &GenerationOptions) -> Self {
+        let mut model_options = Self::default();
+        if let Some(temperature) = options.temperature {
+            model_options = model_options.temperature(temperature);
+        }
+        if let Some(top_p) = options.top_p {
+            model_options = model_options.top_p(top_p);
+        }
+        if let Some(num_ctx) = options.num_ctx {
+            model_options = model_options.num_ctx(num_ctx);
+        }
+        if let Some(seed) = options.seed {
+            model_options = model_options.seed(seed);
+        }
+        model_options
+    }
 }
 
 impl Character {
+    fn validate(&self) {
+        assert!(
+            self.model_name.is_some() != self.base_model.is_some(),
+            "Character \"{}\" must set exactly one of model_name and base_model",
+            self.name
+        );
+        assert!(
+            self.opening_lines.len() >= 2,
+            "Character \"{}\" must have at least two opening lines",
+            self.name
+        );
+        if self.base_model.is_some() && self.system_prompt.is_none() {
+            warn!(
+                "Character \"{}\" uses a base model without a system prompt",
+                self.name
+            );
+        }
+
+        self.receipt.validate();
+    }
+
+    pub(crate) fn availability(&self) -> Availability {
+        match self.availability.load(Ordering::Relaxed) {
+            0 => Availability::Available,
+            1 => Availability::Fallback,
+            _ => Availability::Unavailable,
+        }
+    }
+
+    pub(crate) fn set_availability(&self, availability: Availability) {
+        if availability != self.availability() {
+            info!("{} is now {availability:?}", self.name);
+        }
+        self.availability
+            .store(availability as u8, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_available(&self) -> bool {
+        self.availability() != Availability::Unavailable
+    }
+
+    /// Name of the Ollama model the character runs on when it is working.
+    pub(crate) fn primary_model(&self) -> &str {
+        self.base_model
+            .as_deref()
+            .or(self.model_name.as_deref())
+            .expect("character should have a model")
+    }
+
+    /// Name of the Ollama model the character currently runs on.
+    pub(crate) fn model(&self) -> &str {
+        match (self.availability(), &self.fallback_model) {
+            (Availability::Fallback, Some(fallback)) => fallback,
+            _ => self.primary_model(),
+        }
+    }
+
+    /// Messages that start every conversation with the character.
+    pub(crate) fn initial_history(&self) -> Vec<ChatMessage> {
+        self.system_prompt
+            .iter()
+            .map(|prompt| ChatMessage::system(prompt.clone()))
+            .collect()
+    }
+
+    /// Builds a request to the character's current model, with the character's parameters, and
+    /// records that the model is in use.
+    pub(crate) fn chat_request(&self, messages: Vec<ChatMessage>) -> ChatMessageRequest {
+        *self.last_used.lock().unwrap() = Some(Instant::now());
+        self.chat_request_for(self.model(), messages)
+    }
+
+    /// Whether a request was made to the character's model within the given time.
+    pub(crate) fn used_within(&self, time: Duration) -> bool {
+        self.last_used
+            .lock()
+            .unwrap()
+            .is_some_and(|last_used| last_used.elapsed() < time)
+    }
+
+    /// Builds a request to the given model, with the character's parameters.
+    pub(crate) fn chat_request_for(
+        &self,
+        model: &str,
+        messages: Vec<ChatMessage>,
+    ) -> ChatMessageRequest {
+        let request =
+            ChatMessageRequest::new(model.to_string(), messages).options((&self.options).into());
+        match &self.keep_alive {
+            Some(keep_alive) => request.keep_alive(keep_alive.clone()),
+            None => request,
+        }
+    }
+
     pub(crate) fn text_colour(&self) -> Rgb666 {
         let c: Rgb888 = self.text_colour.clone().into();
         c.into()
@@ -90,9 +337,9 @@ impl Character {
         let lines: Vec<&String> = self.opening_lines.choose_multiple(&mut rng, 3).collect();
         VnOutput {
             response: String::default(),
-            user_reply_1: lines[0].into(),
-            user_reply_2: lines[1].into(),
-            user_reply_3: lines[2].into(),
+            user_replies: lines.into_iter().cloned().collect(),
+            end_conversation: false,
+            farewell: None,
         }
     }
 
@@ -101,11 +348,20 @@ impl Character {
             self.text_colour(),
             self.background_colour(),
             self.border_colour(),
-            last.user_reply_1.as_str().try_into().unwrap(),
-            last.user_reply_2.as_str().try_into().unwrap(),
-            last.user_reply_3.as_str().try_into().unwrap(),
+            last.user_replies
+                .iter()
+                .take(MAX_CHOICES)
+                .map(|c| screen_text(c))
+                .collect(),
         )
     }
+
+    pub(crate) fn reply_screen(&self, last: &VnOutput) -> ReplyScreen {
+        ReplyScreen {
+            response: screen_text(&last.response),
+            choices: self.choice_screen(last),
+        }
+    }
 }
 
 impl From<Character> for CharacterDetails {
@@ -114,8 +370,85 @@ impl From<Character> for CharacterDetails {
             value.text_colour(),
             value.background_colour(),
             value.border_colour(),
-            value.name.as_str().try_into().unwrap(),
-            value.description.as_str().try_into().unwrap(),
+            screen_text(&value.name),
+            screen_text(&value.description),
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_characters_are_valid() {
+        let characters = CharacterCollection::load(Path::new("extra/characters.toml"));
+        let glados = characters
+            .characters
+            .iter()
+            .find(|c| c.name == "GLaDOS")
+            .unwrap();
+        assert_eq!(glados.model(), "gemma3:12b");
+        assert_eq!(glados.initial_history().len(), 1);
+        assert_eq!(glados.options.temperature, Some(0.9));
+    }
+
+    #[test]
+    fn fallback_model() {
+        let characters = CharacterCollection::load(Path::new("extra/characters.toml"));
+        let mut ember = characters.characters[0].clone();
+        assert_eq!(ember.model(), "ember");
+
+        ember.set_availability(Availability::Fallback);
+        assert_eq!(ember.model(), "ember");
+
+        ember.fallback_model = Some("gemma3:4b".to_string());
+        assert_eq!(ember.model(), "gemma3:4b");
+        assert_eq!(ember.primary_model(), "ember");
+
+        ember.set_availability(Availability::Available);
+        assert_eq!(ember.model(), "ember");
+    }
+
+    #[test]
+    fn last_used() {
+        let characters = CharacterCollection::load(Path::new("extra/characters.toml"));
+        let ember = &characters.characters[0];
+        assert!(!ember.used_within(Duration::from_secs(60)));
+
+        ember.clone().chat_request(Vec::new());
+        assert!(ember.used_within(Duration::from_secs(60)));
+        assert!(!ember.used_within(Duration::ZERO));
+
+        // Requests built for a given model, as warm-up does, do not count as a use
+        assert!(!characters.characters[1].used_within(Duration::from_secs(60)));
+        characters.characters[1].chat_request_for("ember", Vec::new());
+        assert!(!characters.characters[1].used_within(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn unavailable_characters_are_skipped() {
+        let characters = CharacterCollection::load(Path::new("extra/characters.toml"));
+        let names = |idx| characters.pick_subset(idx).map(|c| c.name.as_str());
+        assert_eq!(names(0), ["GLaDOS", "Ember", "Mia"]);
+
+        characters.characters[1]
+            .clone()
+            .set_availability(Availability::Unavailable);
+        characters.characters[4].set_availability(Availability::Fallback);
+        assert_eq!(names(0), ["GLaDOS", "Ember", "Vi"]);
+
+        characters.characters[4].set_availability(Availability::Unavailable);
+        assert_eq!(names(0), ["Marisa", "Ember", "Vi"]);
+        assert_eq!(characters.step(2, -1), 0);
+
+        for character in &characters.characters[2..4] {
+            character.set_availability(Availability::Unavailable);
+        }
+        assert_eq!(names(0), ["Ember", "Ember", "Ember"]);
+        assert_eq!(characters.first_available(), Some(0));
+
+        characters.characters[0].set_availability(Availability::Unavailable);
+        assert_eq!(characters.first_available(), None);
+    }
+}
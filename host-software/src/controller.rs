@@ -1,30 +1,55 @@
-use icd::{ButtonAction, CharacterSelectScreen, ChoiceScreen, Screen};
-use log::{debug, info};
+use crate::{text::screen_text, validation::MIN_CHOICES};
+use icd::{
+    AttentionScreen, ButtonAction, CharacterSelectScreen, ChoiceScreen, DeviceInfo, ReplyScreen,
+    Screen, ScreenKind, ScreenReport, ScreensaverSettings, TextFit,
+};
+use log::{debug, info, warn};
 use postcard_rpc::{
     header::VarSeqKind,
-    host_client::HostClient,
-    standard_icd::{PingEndpoint, WireError, ERROR_PATH},
+    host_client::{HostClient, HostErr},
+    standard_icd::{WireError, ERROR_PATH},
 };
 
 pub struct Client {
     pub client: HostClient<WireError>,
+    pub info: DeviceInfo,
 }
 
 impl Client {
-    pub fn new() -> Self {
-        info!("Connecting to USB device...");
+    /// Connects to the controller with the given USB serial number (or the first one found if not
+    /// given) and checks that its firmware speaks the same ICD as this build of the host.
+    pub async fn connect(serial: Option<&str>) -> Self {
+        info!("Connecting to USB device (serial: {serial:?})...");
         let client = HostClient::new_raw_nusb(
-            |d| d.product_string() == Some("llm-vn-controller"),
+            |d| {
+                d.product_string() == Some("llm-vn-controller")
+                    && serial.is_none_or(|serial| d.serial_number() == Some(serial))
+            },
             ERROR_PATH,
             8,
             VarSeqKind::Seq2,
         );
         info!("Connected");
-        Self { client }
+
+        let info = check_controller(&client).await;
+
+        Self { client, info }
+    }
+
+    pub(crate) async fn set_brightness(&self, brightness: u8) {
+        debug!("Setting display brightness: {brightness}");
+        self.client
+            .send_resp::<icd::SetBrightness>(&brightness)
+            .await
+            .unwrap();
     }
 
-    pub(crate) async fn ping(&self, id: u32) -> u32 {
-        self.client.send_resp::<PingEndpoint>(&id).await.unwrap()
+    pub(crate) async fn set_screensaver(&self, settings: ScreensaverSettings) {
+        debug!("Setting screensaver: {settings:?}");
+        self.client
+            .send_resp::<icd::SetScreensaver>(&settings)
+            .await
+            .unwrap();
     }
 
     pub(crate) async fn wait_for_button_push(&self) -> ButtonAction {
@@ -46,11 +71,109 @@ impl Client {
             .unwrap();
     }
 
-    pub(crate) async fn show_choice_screen(&self, screen: ChoiceScreen) {
+    pub(crate) async fn show_choice_screen(&self, screen: ChoiceScreen) -> ScreenReport {
         debug!("Showing choice screen: {screen:?}");
-        self.client
+        let labels: Vec<String> = choice_labels(screen.choices().len()).collect();
+        let report = self
+            .client
             .send_resp::<icd::SetDisplay>(&Screen::Choices(screen))
             .await
             .unwrap();
+
+        log_report(&report, &labels);
+        report
+    }
+
+    pub(crate) async fn show_reply_screen(&self, screen: ReplyScreen) -> ScreenReport {
+        debug!("Showing reply screen: {screen:?}");
+        let labels: Vec<String> = std::iter::once("Reply".to_string())
+            .chain(choice_labels(screen.choices.choices().len()))
+            .collect();
+        let report = self
+            .client
+            .send_resp::<icd::SetDisplay>(&Screen::Reply(screen))
+            .await
+            .unwrap();
+
+        log_report(&report, &labels);
+        report
+    }
+
+    pub(crate) async fn show_attention_screen(&self, title: &str, message: &str) {
+        if !self.info.supports(ScreenKind::Attention) {
+            warn!("Controller cannot show attention screen: {title}");
+            return;
+        }
+
+        let screen = AttentionScreen {
+            title: screen_text(title),
+            message: screen_text(message),
+        };
+
+        self.client
+            .send_resp::<icd::SetDisplay>(&Screen::Attention(screen))
+            .await
+            .unwrap();
+    }
+}
+
+async fn device_info(client: &HostClient<WireError>) -> Result<DeviceInfo, HostErr<WireError>> {
+    client.send_resp::<icd::GetDeviceInfo>(&()).await
+}
+
+/// Checks that the controller firmware speaks the same ICD as this build of the host.
+async fn check_controller(client: &HostClient<WireError>) -> DeviceInfo {
+    let info = device_info(client)
+        .await
+        .expect("Controller should report device info, the firmware is likely too old");
+    info!("Controller: {info:?}");
+
+    assert!(
+        info.schema_hash == icd::SCHEMA_HASH,
+        "Controller ICD schema ({:016x}) does not match host ({:016x}), reflash the controller",
+        info.schema_hash,
+        icd::SCHEMA_HASH,
+    );
+
+    for kind in [ScreenKind::CharacterSelect, ScreenKind::Choices] {
+        assert!(
+            info.supports(kind),
+            "Controller does not support required screen {kind:?}"
+        );
+    }
+
+    assert!(
+        info.max_choices() >= MIN_CHOICES,
+        "Controller has {} buttons, it needs at least {} (one per choice and one to end the conversation)",
+        info.button_count,
+        MIN_CHOICES + 1,
+    );
+
+    if info.firmware_version != env!("CARGO_PKG_VERSION") {
+        warn!(
+            "Controller firmware version {} differs from host version {}",
+            info.firmware_version,
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+
+    info
+}
+
+/// Names each of the choices shown, for [`log_report`].
+fn choice_labels(count: usize) -> impl Iterator<Item = String> {
+    (1..=count).map(|n| format!("Choice {n}"))
+}
+
+/// Logs any text that did not fit on the controller screen, `labels` names each block of text in
+/// the order they appear in the report.
+fn log_report(report: &ScreenReport, labels: &[String]) {
+    for (fit, label) in report.text.iter().zip(labels) {
+        match fit {
+            TextFit::Fits => {}
+            TextFit::Shrunk => debug!("{label} only fits on screen in a smaller font"),
+            TextFit::Paged => debug!("{label} is split across pages on screen"),
+            TextFit::Scrolled => warn!("{label} does not fit on screen, scrolling"),
+        }
     }
 }
@@ -1,9 +1,16 @@
-use crate::character::Character;
+use crate::{
+    character::{Availability, Character},
+    history::{self, Compaction},
+    memory::{VisitorId, VisitorMemory},
+    moderation::{Action, Incident, Moderator},
+    validation::{correction_prompt, repair},
+};
+use anyhow::Context;
 use jiff::Timestamp;
-use log::{debug, info};
+use log::{info, warn};
 use ollama_rs::{
     generation::{
-        chat::{request::ChatMessageRequest, ChatMessage},
+        chat::ChatMessage,
         parameters::{FormatType, JsonStructure},
     },
     Ollama,
@@ -12,28 +19,67 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::path::Path;
 
+/// Sent with the visitor's message when the character has reached its `max_turns`.
+const LAST_TURN_PROMPT: &str =
+    "This is your last reply, say goodbye to the user and set end_conversation.";
+
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct Conversation {
     started_at: Timestamp,
+    #[serde(default)]
+    station: String,
     character: Character,
+    /// Set when the character remembers visitors
+    #[serde(default)]
+    visitor: Option<VisitorId>,
     transcript: Vec<TranscriptEntry>,
     history: Vec<ChatMessage>,
+    /// Older turns removed from `history` to keep it within budget
+    #[serde(default)]
+    compactions: Vec<Compaction>,
+    /// Replies flagged by moderation, which are not in `history` or `transcript`
+    #[serde(default)]
+    incidents: Vec<Incident>,
 }
 
 impl Conversation {
-    fn new(character: Character) -> Self {
+    fn new(station: &str, character: Character) -> Self {
         Self {
             started_at: Timestamp::now(),
-            character,
+            station: station.to_string(),
+            visitor: None,
             transcript: Default::default(),
-            history: Default::default(),
+            history: character.initial_history(),
+            character,
+            compactions: Default::default(),
+            incidents: Default::default(),
         }
     }
 
+    pub(crate) fn character(&self) -> &Character {
+        &self.character
+    }
+
+    pub(crate) fn visitor(&self) -> Option<&VisitorId> {
+        self.visitor.as_ref()
+    }
+
+    pub(crate) fn history(&self) -> &[ChatMessage] {
+        &self.history
+    }
+
+    /// Messages the visitor has sent.
+    fn turns(&self) -> usize {
+        self.transcript
+            .iter()
+            .filter(|e| matches!(e, TranscriptEntry::User(_)))
+            .count()
+    }
+
     pub(crate) fn save_in(&self, dir: &Path) -> anyhow::Result<()> {
         let filename = dir.join(format!(
-            "{0:.0} - {1}.json",
-            self.started_at, self.character.name,
+            "{0:.0} - {1} - {2}.json",
+            self.started_at, self.station, self.character.name,
         ));
         info!("Saving conversation to {filename:?}");
 
@@ -50,52 +96,61 @@ pub(crate) enum TranscriptEntry {
     User(String),
 }
 
-#[derive(JsonSchema, Deserialize, Debug)]
+// Field docs are part of the schema the models reply with
+#[derive(JsonSchema, Serialize, Deserialize, Debug, Clone)]
 pub(crate) struct VnOutput {
     pub response: String,
-    pub user_reply_1: String,
-    pub user_reply_2: String,
-    pub user_reply_3: String,
-}
-
-impl VnOutput {
-    pub(crate) fn is_end_of_conversation(&self) -> bool {
-        self.user_reply_1.is_empty() || self.user_reply_2.is_empty() || self.user_reply_3.is_empty()
-    }
-
-    pub(crate) fn sanitise(self) -> Self {
-        fn sanitise_string(s: String) -> String {
-            s.replace("’", "'")
-                .replace("–", "-")
-                .replace("…", "...")
-                .replace("—", "-")
-        }
-
-        Self {
-            response: sanitise_string(self.response),
-            user_reply_1: sanitise_string(self.user_reply_1),
-            user_reply_2: sanitise_string(self.user_reply_2),
-            user_reply_3: sanitise_string(self.user_reply_3),
-        }
-    }
+    /// Things the user could say back
+    #[schemars(length(min = 2, max = 4))]
+    pub user_replies: Vec<String>,
+    /// True to end the conversation after this response
+    pub end_conversation: bool,
+    /// What to say as the conversation ends
+    #[serde(default)]
+    pub farewell: Option<String>,
 }
 
 pub(crate) struct ConversationClient {
     client: Ollama,
     conversation: Conversation,
     format: FormatType,
+    /// What the character remembers about the visitor, given along with the first message
+    memory: Option<VisitorMemory>,
+    moderator: Moderator,
+    /// Most choices the controller can show
+    max_choices: usize,
 }
 
 impl ConversationClient {
-    pub(crate) fn new(client: &Ollama, character: Character) -> Self {
+    pub(crate) fn new(
+        client: &Ollama,
+        station: &str,
+        character: Character,
+        moderator: Moderator,
+        max_choices: usize,
+    ) -> Self {
         let format = FormatType::StructuredJson(JsonStructure::new::<VnOutput>());
         Self {
             client: client.clone(),
-            conversation: Conversation::new(character),
+            conversation: Conversation::new(station, character),
             format,
+            memory: None,
+            moderator,
+            max_choices,
         }
     }
 
+    /// Identifies the visitor, so the character can remember them next time, along with anything
+    /// it remembers from last time.
+    pub(crate) fn set_visitor(&mut self, visitor: VisitorId, memory: Option<VisitorMemory>) {
+        self.conversation.visitor = Some(visitor);
+        self.memory = memory;
+    }
+
+    pub(crate) fn visitor(&self) -> Option<&VisitorId> {
+        self.conversation.visitor()
+    }
+
     pub(crate) fn conversation(self) -> Conversation {
         self.conversation
     }
@@ -104,36 +159,200 @@ impl ConversationClient {
         &self.conversation.character
     }
 
-    pub(crate) async fn interact(&mut self, user_message: String) -> VnOutput {
+    /// Sends the visitor's message and returns the character's reply, once it has passed
+    /// moderation.
+    pub(crate) async fn interact(&mut self, user_message: String) -> anyhow::Result<VnOutput> {
         self.conversation
             .transcript
             .push(TranscriptEntry::User(user_message.clone()));
 
+        if let Some(compaction) = history::compact(
+            &self.client,
+            &self.conversation.character,
+            &mut self.conversation.history,
+        )
+        .await
+        {
+            self.conversation.compactions.push(compaction);
+        }
+
         let user_message = ChatMessage::user(user_message);
         info!("{user_message:?}");
 
+        // Follows the first message rather than leading the history, which would replace the
+        // model's own system prompt
+        let mut messages = vec![user_message];
+        if let Some(memory) = self.memory.take() {
+            messages.push(ChatMessage::system(memory.prompt()));
+        }
+
+        let last_turn = self
+            .conversation
+            .character
+            .max_turns
+            .is_some_and(|max| self.conversation.turns() >= max);
+        if last_turn {
+            messages.push(ChatMessage::system(LAST_TURN_PROMPT.to_string()));
+        }
+
+        let history_len = self.conversation.history.len();
+        let mut response = self.valid_reply(messages.clone()).await?;
+
+        for attempt in 1.. {
+            let Some(reason) = self.moderator.check(&self.client, &response).await else {
+                break;
+            };
+
+            let action = if attempt <= self.moderator.regenerations {
+                Action::Regenerated
+            } else {
+                Action::Replaced
+            };
+            warn!("Reply flagged ({reason}), {action:?}: {response:?}");
+            self.conversation.incidents.push(Incident {
+                at: Timestamp::now(),
+                output: response,
+                reason,
+                action,
+            });
+
+            self.conversation.history.truncate(history_len);
+            match action {
+                Action::Regenerated => response = self.valid_reply(messages.clone()).await?,
+                Action::Replaced => {
+                    response = self.moderator.replacement(&self.conversation.character);
+                    self.conversation.history.extend(messages);
+                    self.conversation
+                        .history
+                        .push(ChatMessage::assistant(serde_json::to_string(&response)?));
+                    break;
+                }
+            }
+        }
+
+        if last_turn && !response.end_conversation {
+            info!(
+                "Ending conversation after {} turns",
+                self.conversation.turns()
+            );
+            response.end_conversation = true;
+        }
+
+        self.conversation
+            .transcript
+            .push(TranscriptEntry::Character(response.response.clone()));
+        if let Some(farewell) = response
+            .farewell
+            .as_ref()
+            .filter(|_| response.end_conversation)
+        {
+            self.conversation
+                .transcript
+                .push(TranscriptEntry::Character(farewell.clone()));
+        }
+
+        Ok(response)
+    }
+
+    /// Gets a reply from the character, asking it to correct replies that break its limits.
+    ///
+    /// Only the final reply is kept in the history, so the model does not learn from its mistakes.
+    /// If the reply is still invalid once the corrections are used up, it is used anyway, with its
+    /// choices repaired so that the visitor can pick one.
+    async fn valid_reply(&mut self, messages: Vec<ChatMessage>) -> anyhow::Result<VnOutput> {
+        let history_len = self.conversation.history.len();
+        let limits = self.conversation.character.limits.clone();
+
+        let mut response = self.reply(messages.clone()).await?;
+        let mut corrections = 0;
+        loop {
+            let violations = limits.validate(&response, self.max_choices);
+            if violations.is_empty() {
+                break;
+            }
+            if corrections == limits.max_corrections {
+                warn!(
+                    "Reply still invalid after {corrections} corrections, using it: {violations:?}"
+                );
+                let spares = self.conversation.character.starting_phrases().user_replies;
+                repair(&mut response, self.max_choices, &spares);
+                break;
+            }
+
+            corrections += 1;
+            warn!("Reply invalid, asking for correction {corrections}: {violations:?}");
+            response = self
+                .reply(vec![ChatMessage::user(correction_prompt(&violations))])
+                .await?;
+        }
+
+        if corrections > 0 {
+            self.conversation.history.truncate(history_len);
+            self.conversation.history.extend(messages);
+            self.conversation
+                .history
+                .push(ChatMessage::assistant(serde_json::to_string(&response)?));
+        }
+
+        Ok(response)
+    }
+
+    /// Gets a reply from the character's model.
+    ///
+    /// If the character's model fails, its fallback model is tried. The character is marked
+    /// unavailable if there is none, or that fails too.
+    async fn reply(&mut self, messages: Vec<ChatMessage>) -> anyhow::Result<VnOutput> {
+        // Shares its availability with the character being chatted with
+        let character = self.conversation.character.clone();
+        let response = match self.chat(messages.clone()).await {
+            Ok(response) => response,
+            Err(e)
+                if character.fallback_model.is_some()
+                    && character.availability() == Availability::Available =>
+            {
+                warn!(
+                    "{} failed, trying its fallback model: {e:#}",
+                    character.model()
+                );
+                character.set_availability(Availability::Fallback);
+                self.chat(messages)
+                    .await
+                    .inspect_err(|_| character.set_availability(Availability::Unavailable))?
+            }
+            Err(e) => {
+                character.set_availability(Availability::Unavailable);
+                return Err(e);
+            }
+        };
+        info!("{response:?}");
+
+        Ok(response)
+    }
+
+    /// Sends messages to the character's current model, leaving the history as it was if that
+    /// fails.
+    async fn chat(&mut self, messages: Vec<ChatMessage>) -> anyhow::Result<VnOutput> {
+        let history_len = self.conversation.history.len();
+
         let result = self
             .client
             .send_chat_messages_with_history(
                 &mut self.conversation.history,
-                ChatMessageRequest::new(
-                    self.conversation.character.model_name.clone(),
-                    vec![user_message],
-                )
-                .format(self.format.clone()),
+                self.conversation
+                    .character
+                    .chat_request(messages)
+                    .format(self.format.clone()),
             )
             .await
-            .unwrap();
-
-        let response: VnOutput = serde_json::from_str(&result.message.content).unwrap();
-        debug!("Original response: {response:?}");
-        let response = response.sanitise();
-        info!("{response:?}");
-
-        self.conversation
-            .transcript
-            .push(TranscriptEntry::Character(response.response.clone()));
+            .map_err(anyhow::Error::from)
+            .and_then(|result| {
+                serde_json::from_str(&result.message.content)
+                    .with_context(|| format!("Invalid reply {:?}", result.message.content))
+            });
 
-        response
+        if result.is_err() {
+            self.conversation.history.truncate(history_len);
+        }
+        result
     }
 }
@@ -0,0 +1,329 @@
+//! Connections to thermal printers, described by a URI:
+//!
+//! - `serial:///dev/ttyUSB0?baud=38400`: serial port, the baud rate defaults to 38400
+//! - `tcp://host:9100`: network printer, the port defaults to 9100, IPv6 addresses are given in
+//!   brackets as `tcp://[::1]:9100`
+//! - `usb://0416:5011`: USB printer, by vendor and product ID in hex
+//! - `file:///tmp/receipts.bin`: appends raw ESC/POS to a file, for testing without a printer
+
+use anyhow::{anyhow, bail, Context};
+use escpos::{
+    driver::{Driver, FileDriver, NativeUsbDriver, NetworkDriver, SerialPortDriver},
+    errors::{PrinterError, Result},
+};
+use serde::Deserialize;
+use std::{
+    fmt,
+    net::{Ipv6Addr, ToSocketAddrs},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+const DEFAULT_BAUD: u32 = 38400;
+const DEFAULT_TCP_PORT: u16 = 9100;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub(crate) enum PrinterUri {
+    Serial { path: String, baud: u32 },
+    Tcp { host: String, port: u16 },
+    Usb { vendor_id: u16, product_id: u16 },
+    File { path: PathBuf },
+}
+
+impl FromStr for PrinterUri {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (scheme, rest) = s
+            .split_once("://")
+            .ok_or_else(|| anyhow!("Printer URI \"{s}\" has no scheme"))?;
+
+        match scheme {
+            "serial" => {
+                let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+                let mut baud = DEFAULT_BAUD;
+                for param in query.split('&').filter(|p| !p.is_empty()) {
+                    match param.split_once('=') {
+                        Some(("baud", value)) => {
+                            baud = value.parse().context("Invalid baud rate")?;
+                        }
+                        _ => bail!("Unknown serial printer option \"{param}\""),
+                    }
+                }
+                if path.is_empty() {
+                    bail!("Serial printer URI has no port");
+                }
+                Ok(Self::Serial {
+                    path: path.to_string(),
+                    baud,
+                })
+            }
+            "tcp" => {
+                let (host, port) = match rest.strip_prefix('[') {
+                    Some(bracketed) => {
+                        let (host, port) = bracketed
+                            .split_once(']')
+                            .ok_or_else(|| anyhow!("TCP printer URI has no closing \"]\""))?;
+                        if !port.is_empty() && !port.starts_with(':') {
+                            bail!("TCP printer URI has no \":\" before the port");
+                        }
+                        (host, port.strip_prefix(':'))
+                    }
+                    // A bare IPv6 address, which cannot be followed by a port
+                    None if rest.parse::<Ipv6Addr>().is_ok() => (rest, None),
+                    None => match rest.rsplit_once(':') {
+                        Some((host, port)) => (host, Some(port)),
+                        None => (rest, None),
+                    },
+                };
+                let port = match port {
+                    Some(port) => port.parse().context("Invalid TCP port")?,
+                    None => DEFAULT_TCP_PORT,
+                };
+                if host.is_empty() {
+                    bail!("TCP printer URI has no host");
+                }
+                Ok(Self::Tcp {
+                    host: host.to_string(),
+                    port,
+                })
+            }
+            "usb" => {
+                let (vendor_id, product_id) = rest
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("USB printer URI must be usb://vid:pid"))?;
+                Ok(Self::Usb {
+                    vendor_id: u16::from_str_radix(vendor_id, 16).context("Invalid vendor ID")?,
+                    product_id: u16::from_str_radix(product_id, 16)
+                        .context("Invalid product ID")?,
+                })
+            }
+            "file" => {
+                if rest.is_empty() {
+                    bail!("File printer URI has no path");
+                }
+                Ok(Self::File { path: rest.into() })
+            }
+            _ => bail!("Unknown printer URI scheme \"{scheme}\""),
+        }
+    }
+}
+
+impl TryFrom<String> for PrinterUri {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<Self> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for PrinterUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Serial { path, baud } => write!(f, "serial://{path}?baud={baud}"),
+            Self::Tcp { host, port } if host.contains(':') => write!(f, "tcp://[{host}]:{port}"),
+            Self::Tcp { host, port } => write!(f, "tcp://{host}:{port}"),
+            Self::Usb {
+                vendor_id,
+                product_id,
+            } => write!(f, "usb://{vendor_id:04x}:{product_id:04x}"),
+            Self::File { path } => write!(f, "file://{}", path.display()),
+        }
+    }
+}
+
+impl PrinterUri {
+    pub(crate) fn open(&self) -> anyhow::Result<PrinterDriver> {
+        Ok(match self {
+            Self::Serial { path, baud } => {
+                PrinterDriver::Serial(SerialPortDriver::open(path, *baud, Some(TIMEOUT))?)
+            }
+            Self::Tcp { host, port } => {
+                // The driver only accepts IP addresses when given a timeout
+                let addr = (host.as_str(), *port)
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| PrinterError::Io(format!("\"{host}\" has no address")))?;
+                PrinterDriver::Network(NetworkDriver::open(
+                    &addr.ip().to_string(),
+                    *port,
+                    Some(TIMEOUT),
+                )?)
+            }
+            Self::Usb {
+                vendor_id,
+                product_id,
+            } => PrinterDriver::Usb(NativeUsbDriver::open(*vendor_id, *product_id)?),
+            Self::File { path } => {
+                // The driver only appends to an existing file
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)?;
+                PrinterDriver::File(FileDriver::open(path)?)
+            }
+        })
+    }
+}
+
+/// A connection to a printer, which can be handed to a blocking task to wait on its responses.
+pub(crate) trait PrinterConnection: Driver + Clone + Send + 'static {
+    /// Whether the connection can read status responses from the printer.
+    fn reports_status(&self) -> bool;
+}
+
+#[derive(Clone)]
+pub(crate) enum PrinterDriver {
+    Serial(SerialPortDriver),
+    Network(NetworkDriver),
+    Usb(NativeUsbDriver),
+    File(FileDriver),
+}
+
+impl PrinterDriver {
+    fn inner(&self) -> &dyn Driver {
+        match self {
+            Self::Serial(d) => d,
+            Self::Network(d) => d,
+            Self::Usb(d) => d,
+            Self::File(d) => d,
+        }
+    }
+}
+
+impl Driver for PrinterDriver {
+    fn name(&self) -> String {
+        self.inner().name()
+    }
+
+    fn write(&self, data: &[u8]) -> Result<()> {
+        self.inner().write(data)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        self.inner().read(buf)
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.inner().flush()
+    }
+}
+
+impl PrinterConnection for PrinterDriver {
+    fn reports_status(&self) -> bool {
+        !matches!(self, Self::File(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serial() {
+        assert_eq!(
+            "serial:///dev/ttyUSB0?baud=19200"
+                .parse::<PrinterUri>()
+                .unwrap(),
+            PrinterUri::Serial {
+                path: "/dev/ttyUSB0".to_string(),
+                baud: 19200
+            }
+        );
+        assert_eq!(
+            "serial:///dev/ttyUSB0".parse::<PrinterUri>().unwrap(),
+            PrinterUri::Serial {
+                path: "/dev/ttyUSB0".to_string(),
+                baud: DEFAULT_BAUD
+            }
+        );
+        assert!("serial:///dev/ttyUSB0?speed=9600"
+            .parse::<PrinterUri>()
+            .is_err());
+    }
+
+    #[test]
+    fn tcp() {
+        assert_eq!(
+            "tcp://printer.local:9101".parse::<PrinterUri>().unwrap(),
+            PrinterUri::Tcp {
+                host: "printer.local".to_string(),
+                port: 9101
+            }
+        );
+        assert_eq!(
+            "tcp://192.168.1.50".parse::<PrinterUri>().unwrap(),
+            PrinterUri::Tcp {
+                host: "192.168.1.50".to_string(),
+                port: DEFAULT_TCP_PORT
+            }
+        );
+    }
+
+    #[test]
+    fn tcp_ipv6() {
+        assert_eq!(
+            "tcp://[::1]:9101".parse::<PrinterUri>().unwrap(),
+            PrinterUri::Tcp {
+                host: "::1".to_string(),
+                port: 9101
+            }
+        );
+        for uri in ["tcp://[fe80::1]", "tcp://fe80::1"] {
+            assert_eq!(
+                uri.parse::<PrinterUri>().unwrap(),
+                PrinterUri::Tcp {
+                    host: "fe80::1".to_string(),
+                    port: DEFAULT_TCP_PORT
+                }
+            );
+        }
+        assert!("tcp://[::1".parse::<PrinterUri>().is_err());
+        assert!("tcp://[::1]9100".parse::<PrinterUri>().is_err());
+    }
+
+    #[test]
+    fn usb() {
+        assert_eq!(
+            "usb://0416:5011".parse::<PrinterUri>().unwrap(),
+            PrinterUri::Usb {
+                vendor_id: 0x0416,
+                product_id: 0x5011
+            }
+        );
+        assert!("usb://0416".parse::<PrinterUri>().is_err());
+    }
+
+    #[test]
+    fn file() {
+        assert_eq!(
+            "file:///tmp/receipts.bin".parse::<PrinterUri>().unwrap(),
+            PrinterUri::File {
+                path: "/tmp/receipts.bin".into()
+            }
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!("/dev/ttyUSB0".parse::<PrinterUri>().is_err());
+        assert!("lpt://1".parse::<PrinterUri>().is_err());
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for uri in [
+            "serial:///dev/ttyUSB0?baud=38400",
+            "tcp://printer.local:9100",
+            "tcp://[::1]:9100",
+            "usb://0416:5011",
+            "file:///tmp/receipts.bin",
+        ] {
+            assert_eq!(uri.parse::<PrinterUri>().unwrap().to_string(), uri);
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! Checks made before the stations start, so that problems are found by whoever is setting up the
+//! kiosk rather than by the first visitor.
+
+use crate::{
+    character::{Availability, Character, CharacterCollection},
+    controller,
+    driver::PrinterConnection,
+    models,
+    printer::Printer,
+};
+use log::{info, warn};
+use ollama_rs::{models::LocalModel, Ollama};
+use std::{
+    fmt,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// How long Ollama has to list its models
+const OLLAMA_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Status {
+    Ok,
+    Warning,
+    Failed,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Ok => "OK",
+            Self::Warning => "WARN",
+            Self::Failed => "FAIL",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, status: Status, detail: impl Into<String>) -> Self {
+        let check = Self {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+        };
+        match check.status {
+            Status::Ok => info!("Health check {}: {}", check.name, check.detail),
+            Status::Warning | Status::Failed => {
+                warn!(
+                    "Health check {} {}: {}",
+                    check.name, check.status, check.detail
+                )
+            }
+        }
+        check
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HealthReport {
+    pub checks: Vec<Check>,
+}
+
+impl HealthReport {
+    /// Checks that do not pass.
+    pub(crate) fn problems(&self) -> impl Iterator<Item = &Check> {
+        self.checks.iter().filter(|c| c.status != Status::Ok)
+    }
+
+    /// Adds checks made for a single station.
+    pub(crate) fn with(&self, checks: impl IntoIterator<Item = Check>) -> Self {
+        let mut report = self.clone();
+        report.checks.extend(checks);
+        report
+    }
+}
+
+/// Lists the models on the Ollama server.
+pub(crate) async fn check_ollama(ollama: &Ollama) -> (Check, Vec<LocalModel>) {
+    match tokio::time::timeout(OLLAMA_TIMEOUT, ollama.list_local_models()).await {
+        Ok(Ok(models)) if models.is_empty() => {
+            (Check::new("ollama", Status::Failed, "no models"), models)
+        }
+        Ok(Ok(models)) => (
+            Check::new("ollama", Status::Ok, format!("{} models", models.len())),
+            models,
+        ),
+        Ok(Err(e)) => (
+            Check::new("ollama", Status::Failed, e.to_string()),
+            Vec::new(),
+        ),
+        Err(_) => (
+            Check::new("ollama", Status::Failed, "did not respond"),
+            Vec::new(),
+        ),
+    }
+}
+
+/// Checks that conversations can be saved, by writing and removing a file.
+pub(crate) fn check_conversation_directory(dir: &Path) -> Check {
+    let path = dir.join(".health-check");
+    let result = std::fs::write(&path, []).and_then(|_| std::fs::remove_file(&path));
+
+    match result {
+        Ok(()) => Check::new("conversations", Status::Ok, "writable"),
+        Err(e) => Check::new("conversations", Status::Failed, e.to_string()),
+    }
+}
+
+/// Checks that the character's model (or else its fallback model) exists and replies in time,
+/// updating the character's availability to match.
+pub(crate) async fn check_character(
+    ollama: &Ollama,
+    character: &Character,
+    models: &[LocalModel],
+    max_choices: usize,
+    budget: Duration,
+) -> Check {
+    let name = format!("character {}", character.name);
+
+    let error = match check_model(
+        ollama,
+        character,
+        character.primary_model(),
+        models,
+        max_choices,
+        budget,
+    )
+    .await
+    {
+        Ok(latency) => {
+            character.set_availability(Availability::Available);
+            return Check::new(
+                name,
+                Status::Ok,
+                format!("replied in {:.1} s", latency.as_secs_f32()),
+            );
+        }
+        Err(e) => e,
+    };
+
+    if let Some(fallback) = &character.fallback_model {
+        if let Ok(latency) =
+            check_model(ollama, character, fallback, models, max_choices, budget).await
+        {
+            character.set_availability(Availability::Fallback);
+            return Check::new(
+                name,
+                Status::Warning,
+                format!(
+                    "{error}, {fallback} replied in {:.1} s",
+                    latency.as_secs_f32()
+                ),
+            );
+        }
+    }
+
+    character.set_availability(Availability::Unavailable);
+    Check::new(name, Status::Failed, error)
+}
+
+/// Checks that a model exists and replies as the character in time, returning how long it took.
+async fn check_model(
+    ollama: &Ollama,
+    character: &Character,
+    model: &str,
+    models: &[LocalModel],
+    max_choices: usize,
+    budget: Duration,
+) -> Result<Duration, String> {
+    if models::find(models, model).is_none() {
+        return Err(format!("no model {model}"));
+    }
+
+    let start = Instant::now();
+    match tokio::time::timeout(
+        budget,
+        models::verify(ollama, character, model, max_choices),
+    )
+    .await
+    {
+        Ok(Ok(_)) => Ok(start.elapsed()),
+        Ok(Err(e)) => Err(format!("{model}: {e:#}")),
+        Err(_) => Err(format!("{model}: no reply within {} s", budget.as_secs())),
+    }
+}
+
+/// Checks again each character that is not fully working, so that they come back once their
+/// model does.
+pub(crate) async fn recheck_characters(
+    ollama: &Ollama,
+    characters: &CharacterCollection,
+    max_choices: usize,
+    budget: Duration,
+) {
+    let degraded: Vec<&Character> = characters
+        .characters
+        .iter()
+        .filter(|c| c.availability() != Availability::Available)
+        .collect();
+    if degraded.is_empty() {
+        return;
+    }
+
+    let (_, models) = check_ollama(ollama).await;
+    for character in degraded {
+        check_character(ollama, character, &models, max_choices, budget).await;
+    }
+}
+
+/// Checks that the moderation classifier model exists, without it every reply is replaced.
+pub(crate) fn check_classifier(model: &str, models: &[LocalModel]) -> Check {
+    match models::find(models, model) {
+        Some(_) => Check::new("classifier", Status::Ok, model),
+        None => Check::new("classifier", Status::Failed, format!("no model {model}")),
+    }
+}
+
+/// Checks that the printer is responding and has paper.
+pub(crate) async fn check_printer<D: PrinterConnection>(printer: &Printer<D>) -> Check {
+    let status = printer.status().await;
+    match status.problem() {
+        Some(problem) => Check::new("printer", Status::Failed, problem),
+        None if status.paper_low => Check::new("printer", Status::Warning, "paper low"),
+        None => Check::new("printer", Status::Ok, "ready"),
+    }
+}
+
+/// Checks that the controller firmware was built from the same version as the host.
+pub(crate) fn check_controller(controller: &controller::Client) -> Check {
+    let version = &controller.info.firmware_version;
+    if version == env!("CARGO_PKG_VERSION") {
+        Check::new("controller", Status::Ok, format!("firmware {version}"))
+    } else {
+        Check::new(
+            "controller",
+            Status::Warning,
+            format!("firmware {version}, host {}", env!("CARGO_PKG_VERSION")),
+        )
+    }
+}
@@ -0,0 +1,291 @@
+//! Keeps the chat history sent to the model within a token budget, so that long conversations do
+//! not outgrow the model's context or slow down with every turn.
+
+use crate::character::Character;
+use jiff::Timestamp;
+use log::{info, warn};
+use ollama_rs::{
+    generation::chat::{ChatMessage, MessageRole},
+    Ollama,
+};
+use serde::{Deserialize, Serialize};
+
+/// Rough number of characters per token, there is no tokeniser for the models to hand
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Start of the message that replaces summarised turns
+const SUMMARY_PREFIX: &str = "Summary of the earlier conversation:";
+
+const SUMMARY_PROMPT: &str = "Summarise the conversation so far in no more than five sentences, keeping anything you will need to know to carry on with it. Reply with only the summary.";
+
+/// How a character's chat history is kept within budget.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct HistoryLimits {
+    /// Estimated tokens of history above which older turns are compacted
+    pub max_tokens: usize,
+    /// Most recent exchanges (a message and its reply) that are never compacted
+    pub keep_recent: usize,
+    pub strategy: Strategy,
+}
+
+impl Default for HistoryLimits {
+    fn default() -> Self {
+        Self {
+            max_tokens: 3000,
+            keep_recent: 3,
+            strategy: Strategy::Summarise,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Strategy {
+    /// Replace older turns with a summary written by the model
+    Summarise,
+    /// Drop older turns
+    Truncate,
+}
+
+/// A record of older turns being removed from the history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Compaction {
+    pub at: Timestamp,
+    pub strategy: Strategy,
+    pub tokens_before: usize,
+    pub tokens_after: usize,
+    /// Messages removed from the history
+    pub removed: Vec<ChatMessage>,
+    /// Summary that replaced them, if summarised
+    pub summary: Option<String>,
+}
+
+pub(crate) fn estimate_tokens(messages: &[ChatMessage]) -> usize {
+    messages
+        .iter()
+        .map(|m| m.content.chars().count().div_ceil(CHARS_PER_TOKEN))
+        .sum()
+}
+
+fn is_summary(message: &ChatMessage) -> bool {
+    message.role == MessageRole::System && message.content.starts_with(SUMMARY_PREFIX)
+}
+
+/// The history split into what is kept and what may be compacted.
+#[derive(Debug)]
+struct Plan {
+    /// The first message, then any system prompts that followed it
+    head: Vec<ChatMessage>,
+    /// Older turns, including any previous summary
+    old: Vec<ChatMessage>,
+    /// The most recent exchanges
+    recent: Vec<ChatMessage>,
+}
+
+impl Plan {
+    /// Splits the history, if there is anything old enough to compact.
+    ///
+    /// The first message is always kept, so that the history never starts with a system message,
+    /// which Ollama would use in place of the model's own system prompt. System prompts are kept
+    /// too, except for previous summaries which are summarised again.
+    fn new(history: &[ChatMessage], keep_recent: usize) -> Option<Self> {
+        let recent_start = history.len().saturating_sub(keep_recent * 2).max(1);
+        if recent_start <= 1 {
+            return None;
+        }
+
+        let mut head = vec![history[0].clone()];
+        let mut old = Vec::new();
+        for message in &history[1..recent_start] {
+            if message.role == MessageRole::System && !is_summary(message) {
+                head.push(message.clone());
+            } else {
+                old.push(message.clone());
+            }
+        }
+
+        (!old.is_empty()).then(|| Self {
+            head,
+            old,
+            recent: history[recent_start..].to_vec(),
+        })
+    }
+}
+
+/// Compacts older turns of a conversation with the character, if its history is over budget.
+pub(crate) async fn compact(
+    ollama: &Ollama,
+    character: &Character,
+    history: &mut Vec<ChatMessage>,
+) -> Option<Compaction> {
+    let limits = &character.history;
+    let tokens_before = estimate_tokens(history);
+    if tokens_before <= limits.max_tokens {
+        return None;
+    }
+
+    let plan = Plan::new(history, limits.keep_recent)?;
+    info!(
+        "History of ~{tokens_before} tokens is over budget, compacting {} messages",
+        plan.old.len()
+    );
+
+    let summary = match limits.strategy {
+        Strategy::Summarise => summarise(ollama, character, &plan).await,
+        Strategy::Truncate => None,
+    };
+    let strategy = match summary {
+        Some(_) => Strategy::Summarise,
+        None => Strategy::Truncate,
+    };
+
+    *history = plan.head;
+    if let Some(summary) = &summary {
+        history.push(ChatMessage::system(format!("{SUMMARY_PREFIX} {summary}")));
+    }
+    history.extend(plan.recent);
+
+    let tokens_after = estimate_tokens(history);
+    info!("History compacted to ~{tokens_after} tokens");
+
+    Some(Compaction {
+        at: Timestamp::now(),
+        strategy,
+        tokens_before,
+        tokens_after,
+        removed: plan.old,
+        summary,
+    })
+}
+
+/// Asks the model to summarise the older turns, falling back to dropping them if that fails.
+async fn summarise(ollama: &Ollama, character: &Character, plan: &Plan) -> Option<String> {
+    let mut messages = [plan.head.as_slice(), plan.old.as_slice()].concat();
+    messages.push(ChatMessage::user(SUMMARY_PROMPT.to_string()));
+
+    match ollama
+        .send_chat_messages(character.chat_request(messages))
+        .await
+    {
+        Ok(response) => Some(response.message.content.trim().to_string()),
+        Err(e) => {
+            warn!("Failed to summarise history, truncating it instead: {e}");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn character(history: HistoryLimits) -> Character {
+        let mut character: Character = toml::from_str(
+            r#"
+            name = "Test"
+            description = "A test character"
+            model_name = "model"
+            text_colour = { r = 0, g = 0, b = 0 }
+            background_colour = { r = 255, g = 255, b = 255 }
+            border_colour = { r = 0, g = 0, b = 0 }
+            opening_lines = []
+            "#,
+        )
+        .unwrap();
+        character.history = history;
+        character
+    }
+
+    fn contents(messages: &[ChatMessage]) -> Vec<&str> {
+        messages.iter().map(|m| m.content.as_str()).collect()
+    }
+
+    fn conversation(exchanges: usize) -> Vec<ChatMessage> {
+        (0..exchanges)
+            .flat_map(|i| {
+                [
+                    ChatMessage::user(format!("u{i}")),
+                    ChatMessage::assistant(format!("a{i}")),
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn estimate() {
+        assert_eq!(estimate_tokens(&[]), 0);
+        assert_eq!(
+            estimate_tokens(&[
+                ChatMessage::user("abcd".to_string()),
+                ChatMessage::assistant("abcde".to_string())
+            ]),
+            3
+        );
+    }
+
+    #[test]
+    fn nothing_to_compact() {
+        assert!(Plan::new(&conversation(2), 2).is_none());
+        assert!(Plan::new(&conversation(3), 3).is_none());
+    }
+
+    #[test]
+    fn keeps_first_and_recent() {
+        let plan = Plan::new(&conversation(4), 2).unwrap();
+        assert_eq!(contents(&plan.head), ["u0"]);
+        assert_eq!(contents(&plan.old), ["a0", "u1", "a1"]);
+        assert_eq!(contents(&plan.recent), ["u2", "a2", "u3", "a3"]);
+    }
+
+    #[test]
+    fn keeps_system_prompts_but_not_summaries() {
+        let mut history = conversation(4);
+        history.insert(1, ChatMessage::system("memory".to_string()));
+        history.insert(3, ChatMessage::system(format!("{SUMMARY_PREFIX} old")));
+
+        let plan = Plan::new(&history, 1).unwrap();
+        assert_eq!(contents(&plan.head), ["u0", "memory"]);
+        assert_eq!(
+            contents(&plan.old),
+            [
+                "a0",
+                "Summary of the earlier conversation: old",
+                "u1",
+                "a1",
+                "u2",
+                "a2"
+            ]
+        );
+        assert_eq!(contents(&plan.recent), ["u3", "a3"]);
+    }
+
+    #[tokio::test]
+    async fn truncate() {
+        let character = character(HistoryLimits {
+            max_tokens: 4,
+            keep_recent: 1,
+            strategy: Strategy::Truncate,
+        });
+        let mut history = conversation(3);
+
+        let compaction = compact(&Ollama::default(), &character, &mut history)
+            .await
+            .unwrap();
+        assert_eq!(contents(&history), ["u0", "u2", "a2"]);
+        assert_eq!(contents(&compaction.removed), ["a0", "u1", "a1"]);
+        assert_eq!(compaction.tokens_before, 6);
+        assert_eq!(compaction.tokens_after, 3);
+        assert!(compaction.summary.is_none());
+    }
+
+    #[tokio::test]
+    async fn within_budget() {
+        let mut history = conversation(3);
+        let character = character(HistoryLimits::default());
+        assert!(compact(&Ollama::default(), &character, &mut history)
+            .await
+            .is_none());
+        assert_eq!(history.len(), 6);
+    }
+}
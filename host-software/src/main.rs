@@ -1,35 +1,101 @@
 mod character;
 mod controller;
 mod conversation;
+mod driver;
+mod health;
+mod history;
+mod memory;
+mod models;
+mod moderation;
+mod paper;
+mod preview;
 mod printer;
+mod scanner;
+mod station;
+mod template;
+mod text;
+mod validation;
+mod warmup;
 
 use character::{Character, CharacterCollection};
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 use conversation::{Conversation, ConversationClient};
-use escpos::driver::{Driver, SerialPortDriver};
-use icd::{ButtonAction, CharacterSelectScreen};
+use driver::{PrinterConnection, PrinterDriver, PrinterUri};
+use health::HealthReport;
+use icd::{ButtonAction, ScreenKind, ScreensaverSettings, MAX_CHOICES};
 use log::{debug, info, warn};
+use memory::{MemoryStore, VisitorId};
+use moderation::Moderator;
 use ollama_rs::Ollama;
-use printer::Printer;
-use std::{path::PathBuf, time::Duration};
+use paper::PaperTracker;
+use printer::{Printer, PrinterStatus};
+use scanner::Scanner;
+use station::Station;
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use template::ReceiptTemplate;
+use tokio::task::JoinSet;
+use warmup::ModelLoad;
 
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
-    /// Serial port the thermal printer is attached to
-    #[arg(long, env)]
-    printer_serial_port: String,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// Serial baud rate to use when communicating with the thermal printer
-    #[arg(long, env, default_value = "38400")]
-    printer_baud: u32,
+    #[command(flatten)]
+    run: Option<RunArgs>,
+}
 
-    /// Ollama server host
-    #[arg(long, env, default_value = "http://localhost")]
-    ollama_host: String,
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Render a controller screen to a PNG file
+    Preview(preview::PreviewArgs),
 
-    /// Ollama server port
-    #[arg(long, env, default_value = "11434")]
-    ollama_port: u16,
+    /// Manage the Ollama models used by the characters
+    Models(models::ModelsArgs),
+
+    /// Record that a new paper roll has been loaded into a station's printer
+    NewRoll {
+        /// Directory in which state that persists between runs is saved
+        #[arg(long, env, default_value = "/var/lib/llm-vn")]
+        state_directory: PathBuf,
+
+        /// Name of the station, "default" when not using a station file
+        #[arg(long, default_value = "default")]
+        station: String,
+    },
+}
+
+/// Arguments for running the visual novel, used when no subcommand is given
+#[derive(Debug, Args)]
+struct RunArgs {
+    /// File listing the stations to run, each pairing a controller with a printer
+    #[arg(long, env, conflicts_with_all = ["printer", "no_printer", "controller_serial", "visitor_scanner"])]
+    station_file: Option<PathBuf>,
+
+    /// URI of the thermal printer when running a single station, e.g.
+    /// `serial:///dev/ttyUSB0?baud=38400`, `tcp://192.168.1.50:9100`, `usb://0416:5011` or
+    /// `file:///tmp/receipts.bin`
+    #[arg(long, env, required_unless_present_any = ["station_file", "no_printer"])]
+    printer: Option<PrinterUri>,
+
+    /// Run a single station without a thermal printer, the controller shows the whole
+    /// conversation instead
+    #[arg(long, env, conflicts_with = "printer")]
+    no_printer: bool,
+
+    /// USB serial number of the controller to use when running a single station, the first
+    /// controller found is used if not set
+    #[arg(long, env)]
+    controller_serial: Option<String>,
+
+    /// Barcode scanner (sending each scanned code as a line, e.g. `/dev/ttyACM0`) used by returning
+    /// visitors to scan the visitor ID on their receipt, when running a single station
+    #[arg(long, env)]
+    visitor_scanner: Option<PathBuf>,
+
+    #[command(flatten)]
+    ollama: OllamaArgs,
 
     /// File containing character definitions
     #[arg(long, env)]
@@ -38,6 +104,88 @@ struct Cli {
     /// Directory in which to save ended conversations
     #[arg(long, env)]
     conversation_directory: PathBuf,
+
+    /// Directory in which state that persists between runs is saved
+    #[arg(long, env, default_value = "/var/lib/llm-vn")]
+    state_directory: PathBuf,
+
+    /// TOML file describing the layout of the printed receipts, the built in layout is used if
+    /// not set
+    #[arg(long, env)]
+    receipt_template: Option<PathBuf>,
+
+    /// Minutes Ollama keeps a character's model loaded after it was last used (0 to keep it loaded)
+    #[arg(long, env, default_value = "0")]
+    model_keep_alive: u64,
+
+    /// Minutes between loading the characters' models again if they have not been used for as
+    /// long, in case they were unloaded while idle (0 to only load them at startup)
+    #[arg(long, env, default_value = "10")]
+    model_warm_up_interval: u64,
+
+    /// Seconds each character has to reply to the health check at startup, before it is marked
+    /// unavailable
+    #[arg(long, env, default_value = "30")]
+    health_check_latency: u64,
+
+    /// Seconds between checking again characters whose model is not working (0 to disable)
+    #[arg(long, env, default_value = "300")]
+    availability_check_interval: u64,
+
+    /// TOML file listing the words, patterns and classifier model used to check replies, the built
+    /// in checks are used if not set
+    #[arg(long, env)]
+    moderation_file: Option<PathBuf>,
+
+    /// Length of paper on a new printer roll, in metres
+    #[arg(long, env, default_value = "80")]
+    paper_roll_length: f32,
+
+    /// Estimated paper left on a roll below which to warn that it needs changing, in metres
+    #[arg(long, env, default_value = "5")]
+    paper_low_threshold: f32,
+
+    /// Seconds without a button press on the character select screen before cycling through the
+    /// characters to attract visitors (0 to disable)
+    #[arg(long, env, default_value = "60")]
+    attract_timeout: u64,
+
+    /// Seconds each character is shown for while attracting visitors
+    #[arg(long, env, default_value = "8", value_parser = clap::value_parser!(u64).range(1..))]
+    attract_cycle: u64,
+
+    /// Controller display backlight brightness
+    #[arg(long, env, default_value = "255")]
+    display_brightness: u8,
+
+    /// Seconds without a button press before the controller shows its screensaver (0 to disable)
+    #[arg(long, env, default_value = "300")]
+    screensaver_timeout: u32,
+
+    /// Seconds each screensaver card is shown for
+    #[arg(long, env, default_value = "10", value_parser = clap::value_parser!(u32).range(1..))]
+    screensaver_cycle: u32,
+
+    /// Controller display backlight brightness while the screensaver is shown
+    #[arg(long, env, default_value = "32")]
+    screensaver_brightness: u8,
+}
+
+#[derive(Debug, Args)]
+struct OllamaArgs {
+    /// Ollama server host
+    #[arg(long, env, default_value = "http://localhost")]
+    ollama_host: String,
+
+    /// Ollama server port
+    #[arg(long, env, default_value = "11434")]
+    ollama_port: u16,
+}
+
+impl OllamaArgs {
+    fn client(&self) -> Ollama {
+        Ollama::new(self.ollama_host.clone(), self.ollama_port)
+    }
 }
 
 #[tokio::main]
@@ -46,50 +194,422 @@ async fn main() {
 
     env_logger::init();
 
-    let mut printer = Printer::new(
-        SerialPortDriver::open(
-            &args.printer_serial_port,
-            args.printer_baud,
-            Some(Duration::from_secs(5)),
+    match args.command {
+        Some(Command::Preview(args)) => preview::run(args),
+        Some(Command::Models(args)) => models::run(args).await,
+        Some(Command::NewRoll {
+            state_directory,
+            station,
+        }) => paper::new_roll(&PaperTracker::roll_file(&state_directory, &station)),
+        None => {
+            run(args
+                .run
+                .expect("arguments should be required without a subcommand"))
+            .await
+        }
+    }
+}
+
+/// State shared by every station
+struct Kiosk {
+    args: RunArgs,
+    ollama: Ollama,
+    characters: CharacterCollection,
+    model_names: Vec<String>,
+    /// How long each model took to load at startup
+    model_loads: Vec<ModelLoad>,
+    /// Results of the checks made at startup, for all stations
+    health: HealthReport,
+    moderator: Moderator,
+    memory: MemoryStore,
+    template: ReceiptTemplate,
+    /// Most choices every station's controller can show
+    max_choices: usize,
+}
+
+async fn run(args: RunArgs) {
+    let stations = match &args.station_file {
+        Some(path) => station::load(path),
+        None => vec![Station {
+            name: "default".to_string(),
+            controller_serial: args.controller_serial.clone(),
+            printer: args.printer.clone(),
+            visitor_scanner: args.visitor_scanner.clone(),
+        }],
+    };
+
+    let template = match &args.receipt_template {
+        Some(path) => ReceiptTemplate::load(path),
+        None => ReceiptTemplate::default(),
+    };
+
+    let mut ready_stations = Vec::new();
+    for station in stations {
+        info!("Setting up station \"{}\"", station.name);
+
+        let controller = controller::Client::connect(station.controller_serial.as_deref()).await;
+
+        controller.set_brightness(args.display_brightness).await;
+        controller
+            .set_screensaver(ScreensaverSettings {
+                timeout_secs: args.screensaver_timeout,
+                cycle_secs: args.screensaver_cycle,
+                brightness: args.screensaver_brightness,
+            })
+            .await;
+
+        ready_stations.push((station, controller));
+    }
+
+    let max_choices = ready_stations
+        .iter()
+        .map(|(_, controller)| controller.info.max_choices())
+        .min()
+        .unwrap_or(MAX_CHOICES);
+
+    let ollama = args.ollama.client();
+
+    let (ollama_check, models) = health::check_ollama(&ollama).await;
+    info!("Available Ollama models: {models:?}");
+
+    let mut characters = CharacterCollection::load(&args.character_file);
+    characters.set_keep_alive(warmup::keep_alive(args.model_keep_alive));
+
+    warmup::unload_disabled(&ollama, &characters).await;
+    let model_loads = warmup::warm_up(&ollama, &characters, None).await;
+
+    let moderator = match &args.moderation_file {
+        Some(path) => Moderator::load(path),
+        None => Moderator::default(),
+    };
+
+    let mut health = HealthReport::default();
+    health.checks.push(ollama_check);
+    health.checks.push(health::check_conversation_directory(
+        &args.conversation_directory,
+    ));
+    let budget = Duration::from_secs(args.health_check_latency);
+    if let Some(model) = &moderator.classifier_model {
+        health.checks.push(health::check_classifier(model, &models));
+    }
+    for character in &characters.characters {
+        health
+            .checks
+            .push(health::check_character(&ollama, character, &models, max_choices, budget).await);
+    }
+
+    let kiosk = Arc::new(Kiosk {
+        memory: MemoryStore::new(&args.state_directory),
+        args,
+        ollama,
+        characters,
+        model_names: models.into_iter().map(|m| m.name).collect(),
+        model_loads,
+        health,
+        moderator,
+        template,
+        max_choices,
+    });
+
+    if kiosk.args.model_warm_up_interval > 0 {
+        let kiosk = kiosk.clone();
+        tokio::spawn(async move {
+            let period = Duration::from_secs(kiosk.args.model_warm_up_interval * 60);
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            loop {
+                interval.tick().await;
+                debug!("Warming up idle models");
+                warmup::warm_up(&kiosk.ollama, &kiosk.characters, Some(period)).await;
+            }
+        });
+    }
+
+    if kiosk.args.availability_check_interval > 0 {
+        let kiosk = kiosk.clone();
+        tokio::spawn(async move {
+            let period = Duration::from_secs(kiosk.args.availability_check_interval);
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            loop {
+                interval.tick().await;
+                debug!("Checking characters that are not available");
+                health::recheck_characters(
+                    &kiosk.ollama,
+                    &kiosk.characters,
+                    kiosk.max_choices,
+                    Duration::from_secs(kiosk.args.health_check_latency),
+                )
+                .await;
+            }
+        });
+    }
+
+    let mut tasks = JoinSet::new();
+    for (station, controller) in ready_stations {
+        tasks.spawn(run_station(kiosk.clone(), station, controller));
+    }
+
+    // Stations run forever, so this only returns if one of them has failed
+    if let Some(result) = tasks.join_next().await {
+        result.expect("Station should run forever");
+    }
+}
+
+async fn run_station(kiosk: Arc<Kiosk>, station: Station, controller: controller::Client) {
+    const HEALTH_SCREEN_TIMEOUT: Duration = Duration::from_secs(30);
+
+    let mut printer = match &station.printer {
+        Some(uri) => Some(connect_printer(&kiosk, &station, uri, &controller).await),
+        None => {
+            info!(
+                "Station \"{}\" has no printer, the controller will show the whole conversation",
+                station.name
+            );
+            None
+        }
+    };
+
+    let mut checks = vec![health::check_controller(&controller)];
+    if let Some(printer) = &printer {
+        checks.push(health::check_printer(printer).await);
+    }
+    let health = kiosk.health.with(checks);
+
+    let model_names: Vec<&str> = kiosk.model_names.iter().map(String::as_str).collect();
+    print(printer.as_mut(), |p| {
+        p.print_ready(
+            &kiosk.characters.characters,
+            &model_names,
+            &kiosk.model_loads,
+            &health,
+            &controller.info,
         )
-        .unwrap(),
+    });
+
+    let problems: Vec<String> = health
+        .problems()
+        .map(|c| format!("{}: {}", c.name, c.detail))
+        .collect();
+    if !problems.is_empty() {
+        controller
+            .show_attention_screen("Health check", &problems.join("; "))
+            .await;
+        let _ =
+            tokio::time::timeout(HEALTH_SCREEN_TIMEOUT, controller.wait_for_button_push()).await;
+    }
+
+    let attract = AttractMode::new(
+        kiosk.args.attract_timeout,
+        kiosk.args.attract_cycle,
+        kiosk.args.screensaver_timeout,
     );
+    let mut scanner = station.visitor_scanner.clone().map(Scanner::spawn);
+
+    loop {
+        if let Some(printer) = printer.as_mut() {
+            wait_for_printer(printer, &station, &controller).await;
+        }
+
+        let character = select_character(&controller, &kiosk.characters, attract.as_ref()).await;
+
+        // The printer may have run out of paper while waiting for a visitor
+        if let Some(printer) = &printer {
+            if printer.status().await.problem().is_some() {
+                continue;
+            }
+        }
+
+        let claim = scanner.as_mut().and_then(Scanner::take_claim);
 
-    let controller = controller::Client::new();
+        let conversation = converse(
+            printer.as_mut(),
+            &kiosk,
+            &station,
+            &controller,
+            character,
+            claim,
+        )
+        .await;
+        info!(
+            "Conversation ended on station \"{}\": {conversation:#?}",
+            station.name
+        );
+
+        if let Err(e) = conversation.save_in(&kiosk.args.conversation_directory) {
+            warn!("Failed to save conversation: {e}");
+        }
 
-    // Ping controller on start up, just because I suppose...
-    {
-        let req = 42;
-        let resp = controller.ping(req).await;
-        assert!(resp == req, "Controller ping failed");
+        // Summarising takes a while, the next visitor need not wait for it
+        if conversation.visitor().is_some() {
+            let kiosk = kiosk.clone();
+            tokio::spawn(async move { kiosk.memory.update(&kiosk.ollama, &conversation).await });
+        }
     }
+}
+
+/// Connects to the station's printer, asking for help on the controller and trying again until it
+/// responds.
+async fn connect_printer(
+    kiosk: &Kiosk,
+    station: &Station,
+    uri: &PrinterUri,
+    controller: &controller::Client,
+) -> Printer<PrinterDriver> {
+    const RETRY_INTERVAL: Duration = Duration::from_secs(5);
 
-    let ollama = Ollama::new(args.ollama_host, args.ollama_port);
+    let mut shown_problem = false;
 
-    let models = tokio::time::timeout(Duration::from_secs(3), ollama.list_local_models())
+    loop {
+        let opening = uri.clone();
+        let paper = PaperTracker::new(
+            PaperTracker::roll_file(&kiosk.args.state_directory, &station.name),
+            kiosk.args.paper_roll_length,
+            kiosk.args.paper_low_threshold,
+        );
+        let template = kiosk.template.clone();
+        let name = station.name.clone();
+
+        // Opening the connection and printing wait on the printer, so they run on a blocking
+        // thread rather than holding up the other stations
+        let result = tokio::task::spawn_blocking(move || {
+            Printer::new(opening.open()?, paper, template, &name)
+        })
         .await
-        .expect("Ollama list model call should return quickly")
-        .expect("Should be able to list Ollama models");
-    info!("Available Ollama models: {models:?}");
-    if models.is_empty() {
-        panic!("No Ollama models loaded, this is certainly not intended");
+        .unwrap_or_else(|e| Err(e.into()));
+
+        match result {
+            Ok(printer) => {
+                if shown_problem {
+                    info!("Printer on station \"{}\" is connected", station.name);
+                }
+                return printer;
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to connect to printer {uri} on station \"{}\": {e:#}",
+                    station.name
+                );
+                if !shown_problem {
+                    if let Some(problem) = PrinterStatus::NOT_RESPONDING.problem() {
+                        controller
+                            .show_attention_screen("Printer needs attention", problem)
+                            .await;
+                    }
+                    shown_problem = true;
+                }
+            }
+        }
+
+        tokio::time::sleep(RETRY_INTERVAL).await;
     }
+}
 
-    let characters = CharacterCollection::load(&args.character_file);
+/// Waits until the printer is able to print, asking for help on the controller until then.
+async fn wait_for_printer<D: PrinterConnection>(
+    printer: &mut Printer<D>,
+    station: &Station,
+    controller: &controller::Client,
+) {
+    const POLL_INTERVAL: Duration = Duration::from_secs(5);
 
-    let model_names: Vec<&str> = models.iter().map(|m| m.name.as_str()).collect();
-    printer
-        .print_ready(&characters.characters, &model_names)
-        .unwrap();
+    let mut shown_problem = None;
+    let mut was_paper_out = false;
 
     loop {
-        let character = select_character(&controller, &characters).await;
+        let status = printer.status().await;
+        if status.paper_low {
+            warn!(
+                "Printer on station \"{}\" is running low on paper",
+                station.name
+            );
+        }
 
-        let conversation = converse(&mut printer, &ollama, &controller, character).await;
-        info!("Conversation ended: {conversation:#?}");
+        let Some(problem) = status.problem() else {
+            if shown_problem.is_some() {
+                info!("Printer on station \"{}\" is ready again", station.name);
+            }
+            if was_paper_out {
+                printer.new_roll_loaded();
+            }
+            return;
+        };
+        was_paper_out |= status.paper_out;
+
+        if shown_problem != Some(problem) {
+            warn!(
+                "Printer on station \"{}\" needs attention: {problem}",
+                station.name
+            );
+            controller
+                .show_attention_screen("Printer needs attention", problem)
+                .await;
+            shown_problem = Some(problem);
+        }
 
-        if let Err(e) = conversation.save_in(&args.conversation_directory) {
-            warn!("Failed to save conversation: {e}");
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+struct AttractMode {
+    /// Time without a button press before the attract loop starts
+    timeout: Duration,
+    /// Time each character is shown for
+    cycle: Duration,
+    /// Time the loop runs for before the controller's screensaver starts, if it has one
+    until_screensaver: Option<Duration>,
+}
+
+impl AttractMode {
+    fn new(timeout_secs: u64, cycle_secs: u64, screensaver_timeout_secs: u32) -> Option<Self> {
+        (timeout_secs > 0).then(|| Self {
+            timeout: Duration::from_secs(timeout_secs),
+            cycle: Duration::from_secs(cycle_secs),
+            until_screensaver: (screensaver_timeout_secs > 0).then(|| {
+                Duration::from_secs(
+                    u64::from(screensaver_timeout_secs).saturating_sub(timeout_secs),
+                )
+            }),
+        })
+    }
+
+    /// Cycles through the characters until any button is pressed, returning the index of the
+    /// character that was shown at the time.
+    ///
+    /// The button press that ends the loop is consumed, it is not treated as a selection.
+    /// Once the controller's screensaver has started nothing is shown until it is woken, so the
+    /// loop stops and returns the next button press to be acted on, as the controller does not
+    /// pass on the press that wakes it.
+    async fn run(
+        &self,
+        controller: &controller::Client,
+        characters: &CharacterCollection,
+        mut idx: usize,
+    ) -> (usize, Option<ButtonAction>) {
+        info!("Nobody is here, attracting visitors");
+        let start = tokio::time::Instant::now();
+
+        loop {
+            if self
+                .until_screensaver
+                .is_some_and(|until| start.elapsed() >= until)
+            {
+                info!("Controller screensaver has started, ending attract loop");
+                return (idx, Some(controller.wait_for_button_push().await));
+            }
+
+            idx = characters.step(idx, 1);
+            controller
+                .show_character_select_screen(characters.select_screen(idx))
+                .await;
+
+            if tokio::time::timeout(self.cycle, controller.wait_for_button_push())
+                .await
+                .is_ok()
+            {
+                info!("Somebody is here, ending attract loop");
+                return (idx, None);
+            }
         }
     }
 }
@@ -97,43 +617,67 @@ async fn main() {
 async fn select_character(
     controller: &controller::Client,
     characters: &CharacterCollection,
+    attract: Option<&AttractMode>,
 ) -> Character {
+    const UNAVAILABLE_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
     let mut selected_idx = 0;
 
     'character_select: loop {
+        if !characters.characters[selected_idx].is_available() {
+            let Some(idx) = characters.first_available() else {
+                warn!("No characters are available");
+                controller
+                    .show_attention_screen(
+                        "No characters available",
+                        "Check the Ollama server and the ready receipt",
+                    )
+                    .await;
+                tokio::time::sleep(UNAVAILABLE_POLL_INTERVAL).await;
+                continue 'character_select;
+            };
+            selected_idx = idx;
+        }
         debug!("selected_idx = {selected_idx}");
 
-        let charas = characters.pick_subset(selected_idx);
         controller
-            .show_character_select_screen(CharacterSelectScreen {
-                prev: charas[0].clone().into(),
-                selected: charas[1].clone().into(),
-                next: charas[2].clone().into(),
-            })
+            .show_character_select_screen(characters.select_screen(selected_idx))
             .await;
 
-        let button = controller.wait_for_button_push().await;
+        let button = match attract {
+            Some(attract) => {
+                match tokio::time::timeout(attract.timeout, controller.wait_for_button_push()).await
+                {
+                    Ok(button) => button,
+                    Err(_) => {
+                        let (idx, button) = attract.run(controller, characters, selected_idx).await;
+                        selected_idx = idx;
+                        match button {
+                            Some(button) => button,
+                            None => continue 'character_select,
+                        }
+                    }
+                }
+            }
+            None => controller.wait_for_button_push().await,
+        };
 
         match button {
             ButtonAction::Fn1 => {
                 debug!("Previous pressed");
-                if selected_idx == 0 {
-                    selected_idx = characters.characters.len() - 1;
-                } else {
-                    selected_idx = selected_idx.saturating_sub(1);
-                }
+                selected_idx = characters.step(selected_idx, -1);
             }
             ButtonAction::Fn2 => {
-                break 'character_select;
+                // The character may have become unavailable while it was shown
+                if characters.characters[selected_idx].is_available() {
+                    break 'character_select;
+                }
             }
             ButtonAction::Fn3 => {
                 debug!("Next pressed");
-                selected_idx = selected_idx.saturating_add(1);
-                if selected_idx == characters.characters.len() {
-                    selected_idx = 0;
-                }
+                selected_idx = characters.step(selected_idx, 1);
             }
-            ButtonAction::EndConversation => {}
+            ButtonAction::Fn4 | ButtonAction::EndConversation => {}
         }
     }
 
@@ -143,53 +687,150 @@ async fn select_character(
     chara
 }
 
-async fn converse<D: Driver>(
-    printer: &mut Printer<D>,
-    ollama: &Ollama,
+/// Prints part of a receipt, if there is a printer.
+///
+/// Failing to print is not worth stopping for, the conversation is still saved.
+fn print<D: PrinterConnection>(
+    printer: Option<&mut Printer<D>>,
+    f: impl FnOnce(&mut Printer<D>) -> escpos::errors::Result<()>,
+) {
+    if let Some(printer) = printer {
+        if let Err(e) = f(printer) {
+            warn!("Failed to print: {e}");
+        }
+    }
+}
+
+/// Holds a conversation with a visitor.
+///
+/// `claim` is the visitor ID scanned by a returning visitor, if any.
+async fn converse<D: PrinterConnection>(
+    mut printer: Option<&mut Printer<D>>,
+    kiosk: &Kiosk,
+    station: &Station,
     controller: &controller::Client,
     character: Character,
+    claim: Option<VisitorId>,
 ) -> Conversation {
-    let mut conversation = ConversationClient::new(ollama, character.clone());
-    printer.print_chat_header(conversation.character()).unwrap();
+    const BUTTON_TIMEOUT: Duration = Duration::from_secs(60);
+    const UNAVAILABLE_SCREEN_TIME: Duration = Duration::from_secs(10);
+
+    let mut conversation = ConversationClient::new(
+        &kiosk.ollama,
+        &station.name,
+        character.clone(),
+        kiosk.moderator.clone(),
+        controller.info.max_choices(),
+    );
+
+    if character.remember_visitors {
+        let visitor = claim.unwrap_or_else(VisitorId::new);
+        let memory = kiosk.memory.recall(&character.name, &visitor);
+        info!(
+            "Chatting with visitor {visitor}, {} remembers them: {}",
+            character.name,
+            memory.is_some()
+        );
+        conversation.set_visitor(visitor, memory);
+    } else if let Some(claim) = claim {
+        info!(
+            "{} does not remember visitors, ignoring visitor {claim}",
+            character.name
+        );
+    }
+    print(printer.as_deref_mut(), |p| {
+        p.print_chat_header(conversation.character())
+    });
 
     let mut vn_out = character.starting_phrases();
 
+    // Without a printer the replies would not be seen anywhere else
+    let want_reply = character.show_reply || printer.is_none();
+    let show_reply = want_reply && controller.info.supports(ScreenKind::Reply);
+    if want_reply && !show_reply {
+        warn!("Controller cannot show replies, only showing choices");
+    }
+
     'conversation: loop {
-        controller
-            .show_choice_screen(character.choice_screen(&vn_out))
-            .await;
+        if show_reply && !vn_out.response.is_empty() {
+            controller
+                .show_reply_screen(character.reply_screen(&vn_out))
+                .await;
+        } else {
+            controller
+                .show_choice_screen(character.choice_screen(&vn_out))
+                .await;
+        }
 
-        const BUTTON_TIMEOUT: Duration = Duration::from_secs(60);
-        let button = tokio::time::timeout(BUTTON_TIMEOUT, controller.wait_for_button_push())
-            .await
-            .unwrap_or_else(|_| {
-                info!("No button pressed in {BUTTON_TIMEOUT:?}, ending conversation");
-                ButtonAction::EndConversation
-            });
-
-        let user_text = match button {
-            ButtonAction::Fn1 => vn_out.user_reply_1,
-            ButtonAction::Fn2 => vn_out.user_reply_2,
-            ButtonAction::Fn3 => vn_out.user_reply_3,
-            ButtonAction::EndConversation => {
+        let user_text = loop {
+            let button = tokio::time::timeout(BUTTON_TIMEOUT, controller.wait_for_button_push())
+                .await
+                .unwrap_or_else(|_| {
+                    info!("No button pressed in {BUTTON_TIMEOUT:?}, ending conversation");
+                    ButtonAction::EndConversation
+                });
+
+            if let ButtonAction::EndConversation = button {
+                break 'conversation;
+            }
+
+            let count = vn_out.user_replies.len();
+            match button.choice(count, controller.info.max_choices()) {
+                Some(idx) => break vn_out.user_replies.swap_remove(idx),
+                None => debug!("{button:?} does not pick one of {count} choices"),
+            }
+        };
+
+        print(printer.as_deref_mut(), |p| {
+            p.print_user_message(conversation.character(), &user_text)
+        });
+
+        vn_out = match conversation.interact(user_text).await {
+            Ok(vn_out) => vn_out,
+            Err(e) => {
+                warn!(
+                    "{} is unavailable, ending conversation: {e:#}",
+                    character.name
+                );
+                controller
+                    .show_attention_screen(
+                        &format!("{} is unavailable", character.name),
+                        "Sorry! Please choose another character.",
+                    )
+                    .await;
+                tokio::time::sleep(UNAVAILABLE_SCREEN_TIME).await;
                 break 'conversation;
             }
         };
 
-        printer.print_user_message(&user_text).unwrap();
+        print(printer.as_deref_mut(), |p| {
+            p.print_character_message(conversation.character(), &vn_out.response)
+        });
 
-        vn_out = conversation.interact(user_text).await;
+        if vn_out.end_conversation {
+            if let Some(farewell) = vn_out.farewell.take() {
+                print(printer.as_deref_mut(), |p| {
+                    p.print_character_message(conversation.character(), &farewell)
+                });
+                vn_out.response = format!("{}\n{farewell}", vn_out.response);
+            }
 
-        printer
-            .print_character_message(conversation.character(), &vn_out.response)
-            .unwrap();
+            // Nothing is printed, so keep the last reply on screen until the visitor moves on
+            if printer.is_none() && show_reply {
+                controller
+                    .show_reply_screen(character.reply_screen(&vn_out))
+                    .await;
+                let _ =
+                    tokio::time::timeout(BUTTON_TIMEOUT, controller.wait_for_button_push()).await;
+            }
 
-        if vn_out.is_end_of_conversation() {
             break 'conversation;
         }
     }
 
-    printer.print_chat_footer().unwrap();
+    print(printer, |p| {
+        p.print_chat_footer(conversation.character(), conversation.visitor())
+    });
 
     conversation.conversation()
 }
@@ -0,0 +1,189 @@
+//! Long-term memory of visitors, so that characters can recognise somebody they have chatted with
+//! before.
+//!
+//! Receipts from characters that remember visitors carry a visitor ID, scanning it at the start of
+//! a later conversation brings back a summary of the previous ones.
+
+use crate::conversation::Conversation;
+use jiff::Timestamp;
+use log::{info, warn};
+use ollama_rs::{generation::chat::ChatMessage, Ollama};
+use rand::seq::IndexedRandom;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// Characters used in visitor IDs, without any that are easily confused with each other
+const ID_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const ID_LENGTH: usize = 8;
+
+const SUMMARY_PROMPT: &str = "The visitor has left. Write a short summary, no more than five sentences, of what you know about them and what you have talked about (including in any previous conversations), so that you can remember them if they come back. Reply with only the summary.";
+
+/// Identifies a visitor between conversations, printed on their receipt.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct VisitorId(String);
+
+impl VisitorId {
+    pub(crate) fn new() -> Self {
+        let mut rng = rand::rng();
+        Self(
+            (0..ID_LENGTH)
+                .map(|_| char::from(*ID_ALPHABET.choose(&mut rng).unwrap()))
+                .collect(),
+        )
+    }
+
+    /// Reads a visitor ID as scanned or typed, ignoring case and separators.
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        let id: String = s
+            .chars()
+            .filter(|c| *c != '-' && !c.is_whitespace())
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+
+        (id.len() == ID_LENGTH && id.bytes().all(|b| ID_ALPHABET.contains(&b))).then_some(Self(id))
+    }
+}
+
+impl fmt::Display for VisitorId {
+    /// Formats the ID in two halves, to make it easier to read.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (a, b) = self.0.split_at(ID_LENGTH / 2);
+        write!(f, "{a}-{b}")
+    }
+}
+
+/// What a character remembers about a visitor.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct VisitorMemory {
+    pub summary: String,
+    pub conversations: u32,
+    pub updated_at: Timestamp,
+}
+
+impl VisitorMemory {
+    /// Message given to the character so that it remembers the visitor.
+    pub(crate) fn prompt(&self) -> String {
+        format!(
+            "You have chatted with this visitor {} time(s) before. This is what you remember about them:\n{}",
+            self.conversations, self.summary
+        )
+    }
+}
+
+/// Saves what each character remembers about each visitor, one file per visitor.
+#[derive(Debug, Clone)]
+pub(crate) struct MemoryStore {
+    dir: PathBuf,
+}
+
+impl MemoryStore {
+    pub(crate) fn new(state_directory: &Path) -> Self {
+        Self {
+            dir: state_directory.join("memory"),
+        }
+    }
+
+    fn file(&self, character: &str, visitor: &VisitorId) -> PathBuf {
+        self.dir.join(character).join(format!("{}.json", visitor.0))
+    }
+
+    pub(crate) fn recall(&self, character: &str, visitor: &VisitorId) -> Option<VisitorMemory> {
+        let path = self.file(character, visitor);
+        if !path.exists() {
+            return None;
+        }
+
+        let memory = std::fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|s| Ok(serde_json::from_str(&s)?));
+
+        memory
+            .inspect_err(|e| warn!("Failed to read visitor memory from {}: {e}", path.display()))
+            .ok()
+    }
+
+    fn remember(&self, character: &str, visitor: &VisitorId, memory: &VisitorMemory) {
+        let path = self.file(character, visitor);
+        info!("Saving visitor memory to {}", path.display());
+
+        let result = std::fs::create_dir_all(path.parent().unwrap())
+            .map_err(anyhow::Error::from)
+            .and_then(|_| Ok(serde_json::to_string_pretty(memory)?))
+            .and_then(|s| Ok(std::fs::write(&path, s)?));
+
+        if let Err(e) = result {
+            warn!("Failed to save visitor memory to {}: {e}", path.display());
+        }
+    }
+
+    /// Asks the character to summarise an ended conversation and remembers it for next time.
+    pub(crate) async fn update(&self, ollama: &Ollama, conversation: &Conversation) {
+        let Some(visitor) = conversation.visitor() else {
+            return;
+        };
+        if conversation.history().is_empty() {
+            return;
+        }
+
+        let character = conversation.character();
+        let previous = self.recall(&character.name, visitor);
+
+        let mut messages = conversation.history().to_vec();
+        messages.push(ChatMessage::user(SUMMARY_PROMPT.to_string()));
+
+        let summary = match ollama
+            .send_chat_messages(character.chat_request(messages))
+            .await
+        {
+            Ok(response) => response.message.content.trim().to_string(),
+            Err(e) => {
+                warn!("Failed to summarise conversation with visitor {visitor}: {e}");
+                return;
+            }
+        };
+        info!(
+            "{} remembers visitor {visitor} as: {summary}",
+            character.name
+        );
+
+        self.remember(
+            &character.name,
+            visitor,
+            &VisitorMemory {
+                summary,
+                conversations: previous.map_or(0, |m| m.conversations) + 1,
+                updated_at: Timestamp::now(),
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_ids_parse() {
+        let id = VisitorId::new();
+        assert_eq!(VisitorId::parse(&id.to_string()), Some(id.clone()));
+        assert_eq!(VisitorId::parse(&id.0), Some(id));
+    }
+
+    #[test]
+    fn parse_is_forgiving() {
+        assert_eq!(
+            VisitorId::parse(" abcd-efgh\r"),
+            Some(VisitorId("ABCDEFGH".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_rejects_other_codes() {
+        assert_eq!(VisitorId::parse("ABCD-EFG"), None);
+        assert_eq!(VisitorId::parse("ABCD-EFG0"), None);
+        assert_eq!(VisitorId::parse("https://example.com"), None);
+    }
+}
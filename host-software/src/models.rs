@@ -0,0 +1,517 @@
+//! Keeps the models on the Ollama server in step with the characters, creating or updating models
+//! from their Modelfiles and pulling any base models that are missing.
+
+use crate::{
+    character::{Character, CharacterCollection},
+    conversation::VnOutput,
+    OllamaArgs,
+};
+use anyhow::{anyhow, bail, Context};
+use clap::{Args, Subcommand};
+use icd::MAX_CHOICES;
+use ollama_rs::{
+    generation::{
+        chat::ChatMessage,
+        parameters::{FormatType, JsonStructure},
+    },
+    models::{create::CreateModelRequest, LocalModel, ModelOptions},
+    Ollama,
+};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+use tokio_stream::StreamExt;
+
+#[derive(Debug, Args)]
+pub(crate) struct ModelsArgs {
+    #[command(subcommand)]
+    command: ModelsCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum ModelsCommand {
+    /// Create, update or pull only the models that have changed, then check that each character
+    /// replies as expected
+    Sync(SyncArgs),
+}
+
+#[derive(Debug, Args)]
+struct SyncArgs {
+    #[command(flatten)]
+    ollama: OllamaArgs,
+
+    /// File containing character definitions
+    #[arg(long, env)]
+    character_file: PathBuf,
+
+    /// Directory containing a `<model>.Modelfile` for each character model
+    #[arg(long, env)]
+    modelfile_directory: PathBuf,
+
+    /// Only report what would change
+    #[arg(long)]
+    dry_run: bool,
+}
+
+pub(crate) async fn run(args: ModelsArgs) {
+    match args.command {
+        ModelsCommand::Sync(args) => sync(args).await,
+    }
+}
+
+/// The parts of a Modelfile that the characters use.
+#[derive(Debug, Default, PartialEq)]
+struct Modelfile {
+    from: String,
+    system: Option<String>,
+    /// In the order given, a parameter may be given more than once (e.g. `stop`)
+    parameters: Vec<(String, String)>,
+    /// Any other instructions, which cannot be synced
+    other: Vec<String>,
+}
+
+impl Modelfile {
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        content
+            .parse()
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// Parameters in the form used by the Ollama API.
+    fn options(&self) -> anyhow::Result<ModelOptions> {
+        let mut options = serde_json::Map::new();
+        for (key, value) in &self.parameters {
+            let value = parameter_value(value);
+            if key == "stop" {
+                options
+                    .entry(key)
+                    .or_insert_with(|| serde_json::Value::Array(Vec::new()))
+                    .as_array_mut()
+                    .unwrap()
+                    .push(value);
+            } else {
+                options.insert(key.clone(), value);
+            }
+        }
+        serde_json::from_value(options.into()).context("Invalid parameters")
+    }
+
+    /// Whether a model created from this Modelfile would differ from a model on the server.
+    ///
+    /// Parameters are only compared if they are set here, as the server also lists any the model
+    /// inherits from its base model.
+    fn differs_from(&self, server: &Self) -> bool {
+        let system = |m: &Self| m.system.as_deref().map(str::trim).map(str::to_string);
+        if system(self) != system(server) {
+            return true;
+        }
+
+        let keys: BTreeSet<&str> = self.parameters.iter().map(|(k, _)| k.as_str()).collect();
+        keys.into_iter().any(|key| {
+            let values = |m: &Self| {
+                m.parameters
+                    .iter()
+                    .filter(|(k, _)| k == key)
+                    // So that e.g. `0.9`, `0.90` and `1`, `1.0` are treated the same
+                    .map(|(_, v)| v.parse::<f64>().map_or(v.clone(), |f| f.to_string()))
+                    .collect::<Vec<_>>()
+            };
+            values(self) != values(server)
+        })
+    }
+}
+
+impl std::str::FromStr for Modelfile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut modelfile = Self::default();
+        let mut from = None;
+
+        let mut lines = s.lines();
+        while let Some(line) = lines.next() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (instruction, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim();
+
+            // Values in triple quotes may span several lines
+            let value = match rest.strip_prefix(r#"""""#) {
+                Some(start) => {
+                    let mut value = start.to_string();
+                    while !value.ends_with(r#"""""#) {
+                        let line = lines
+                            .next()
+                            .ok_or_else(|| anyhow!("Unterminated \"\"\" in {instruction}"))?;
+                        value.push('\n');
+                        value.push_str(line);
+                    }
+                    value.truncate(value.len() - 3);
+                    value
+                }
+                None => rest.to_string(),
+            };
+
+            match instruction.to_ascii_uppercase().as_str() {
+                "FROM" => from = Some(value),
+                "SYSTEM" => modelfile.system = Some(unquote(&value).to_string()),
+                "PARAMETER" => {
+                    let (key, value) = value
+                        .split_once(char::is_whitespace)
+                        .ok_or_else(|| anyhow!("PARAMETER \"{value}\" has no value"))?;
+                    modelfile
+                        .parameters
+                        .push((key.to_string(), unquote(value.trim()).to_string()));
+                }
+                other => modelfile.other.push(other.to_string()),
+            }
+        }
+
+        modelfile.from = from.ok_or_else(|| anyhow!("Modelfile has no FROM"))?;
+        Ok(modelfile)
+    }
+}
+
+fn unquote(s: &str) -> &str {
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(s)
+}
+
+/// Parses a parameter value as a number or boolean where possible.
+fn parameter_value(value: &str) -> serde_json::Value {
+    if let Ok(i) = value.parse::<i64>() {
+        i.into()
+    } else if let Ok(f) = value.parse::<f64>() {
+        f.into()
+    } else if let Ok(b) = value.parse::<bool>() {
+        b.into()
+    } else {
+        value.into()
+    }
+}
+
+/// Finds a model on the server, a model name without a tag refers to the `latest` tag.
+pub(crate) fn find<'a>(models: &'a [LocalModel], name: &str) -> Option<&'a LocalModel> {
+    let tagged = if name.contains(':') {
+        name.to_string()
+    } else {
+        format!("{name}:latest")
+    };
+    models.iter().find(|m| m.name == tagged)
+}
+
+#[derive(Debug)]
+enum Action {
+    Pull,
+    Create,
+    Update,
+}
+
+async fn sync(args: SyncArgs) {
+    let ollama = args.ollama.client();
+    let characters = CharacterCollection::load(&args.character_file);
+
+    let mut modelfiles = Vec::new();
+    for character in &characters.characters {
+        let Some(model_name) = &character.model_name else {
+            continue;
+        };
+        let path = args
+            .modelfile_directory
+            .join(format!("{model_name}.Modelfile"));
+        let modelfile = Modelfile::load(&path).unwrap_or_else(|e| panic!("{e:#}"));
+        assert!(
+            modelfile.other.is_empty(),
+            "{} uses instructions that cannot be synced: {:?}",
+            path.display(),
+            modelfile.other
+        );
+        modelfiles.push((model_name.as_str(), modelfile));
+    }
+
+    let models = ollama
+        .list_local_models()
+        .await
+        .expect("Should be able to list Ollama models");
+
+    // Base models come first, as the character models are created from them
+    let base_models: BTreeSet<&str> = modelfiles
+        .iter()
+        .map(|(_, m)| m.from.as_str())
+        .chain(
+            characters
+                .characters
+                .iter()
+                .flat_map(|c| [c.base_model.as_deref(), c.fallback_model.as_deref()])
+                .flatten(),
+        )
+        .collect();
+
+    let mut plan = Vec::new();
+    for name in base_models {
+        if find(&models, name).is_none() {
+            plan.push((name, Action::Pull, None));
+        }
+    }
+
+    for (name, modelfile) in &modelfiles {
+        let action = match find(&models, name) {
+            None => Some(Action::Create),
+            Some(_) => match compare(&ollama, name, modelfile).await {
+                Ok(true) => Some(Action::Update),
+                Ok(false) => None,
+                Err(e) => {
+                    println!("Could not compare {name} with the server, recreating it: {e:#}");
+                    Some(Action::Update)
+                }
+            },
+        };
+        match action {
+            Some(action) => plan.push((name, action, Some(modelfile))),
+            None => println!("{name}: up to date"),
+        }
+    }
+
+    for (name, action, modelfile) in &plan {
+        println!("{name}: {action:?}");
+        if args.dry_run {
+            continue;
+        }
+
+        let result = match (action, modelfile) {
+            (Action::Pull, _) => pull(&ollama, name).await,
+            (_, Some(modelfile)) => create(&ollama, name, modelfile).await,
+            (_, None) => unreachable!("only pulls have no Modelfile"),
+        };
+        match result {
+            Ok(status) => println!("{name}: {status}"),
+            Err(e) => panic!("Failed to {action:?} {name}: {e:#}"),
+        }
+    }
+
+    if args.dry_run {
+        return;
+    }
+
+    let mut failed = Vec::new();
+    for character in &characters.characters {
+        let model = character.primary_model();
+        match verify(&ollama, character, model, MAX_CHOICES).await {
+            Ok(output) => println!(
+                "{} ({model}) replies: {:?}",
+                character.name, output.response
+            ),
+            Err(e) => {
+                println!("{} ({model}) failed to reply: {e:#}", character.name);
+                failed.push(character.name.as_str());
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        panic!("Characters did not reply as expected: {failed:?}");
+    }
+}
+
+/// Whether the model on the server differs from its Modelfile, or from the base model it should
+/// have been created from.
+async fn compare(ollama: &Ollama, name: &str, modelfile: &Modelfile) -> anyhow::Result<bool> {
+    let show = |name: &str| {
+        let name = name.to_string();
+        async move {
+            ollama
+                .show_model_info(name)
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|info| info.modelfile.parse::<Modelfile>())
+        }
+    };
+
+    let server = show(name).await?;
+    let base = show(&modelfile.from).await?;
+
+    // Both refer to the weights by their blob on the server
+    Ok(server.from != base.from || modelfile.differs_from(&server))
+}
+
+async fn create(ollama: &Ollama, name: &str, modelfile: &Modelfile) -> anyhow::Result<String> {
+    let mut request = CreateModelRequest::new(name.to_string())
+        .from_model(modelfile.from.clone())
+        .parameters(modelfile.options()?);
+    if let Some(system) = &modelfile.system {
+        request = request.system(system.clone());
+    }
+
+    Ok(ollama.create_model(request).await?.message)
+}
+
+/// Pulls a model, printing each step and how far each layer has downloaded, as large models take
+/// a long time.
+async fn pull(ollama: &Ollama, name: &str) -> anyhow::Result<String> {
+    let mut stream = ollama.pull_model_stream(name.to_string(), false).await?;
+    let mut message = String::new();
+    let mut tenths = 0;
+
+    while let Some(status) = stream.next().await {
+        let status = status?;
+        match (status.completed, status.total) {
+            (Some(completed), Some(total)) if total > 0 => {
+                // Only prints every tenth of a layer, as the server reports progress many times
+                // a second
+                let done = completed * 10 / total;
+                if status.message != message || done != tenths {
+                    println!(
+                        "{name}: {} {}% of {} MB",
+                        status.message,
+                        done * 10,
+                        total / 1_000_000
+                    );
+                    tenths = done;
+                }
+            }
+            _ if status.message != message => println!("{name}: {}", status.message),
+            _ => {}
+        }
+        message = status.message;
+    }
+
+    Ok(message)
+}
+
+/// Checks that the character replies with valid output to one of its opening lines, using the
+/// given model, giving no more than `max_choices` choices.
+pub(crate) async fn verify(
+    ollama: &Ollama,
+    character: &Character,
+    model: &str,
+    max_choices: usize,
+) -> anyhow::Result<VnOutput> {
+    let mut messages = character.initial_history();
+    messages.push(ChatMessage::user(
+        character.starting_phrases().user_replies.remove(0),
+    ));
+
+    let response = ollama
+        .send_chat_messages(
+            character
+                .chat_request_for(model, messages)
+                .format(FormatType::StructuredJson(JsonStructure::new::<VnOutput>())),
+        )
+        .await?;
+
+    let output: VnOutput = serde_json::from_str(&response.message.content)
+        .with_context(|| format!("Invalid reply {:?}", response.message.content))?;
+    if output.end_conversation {
+        bail!("Reply ends the conversation straight away: {output:?}");
+    }
+    let violations = character.limits.validate(&output, max_choices);
+    if !violations.is_empty() {
+        bail!("Reply is invalid ({}): {output:?}", violations.join(" "));
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODELFILE: &str = r#"FROM gemma3:12b
+
+PARAMETER temperature 0.9
+
+SYSTEM """You are Ember.
+Reply in no more than two sentences.
+"""
+"#;
+
+    #[test]
+    fn parse() {
+        let modelfile: Modelfile = MODELFILE.parse().unwrap();
+        assert_eq!(modelfile.from, "gemma3:12b");
+        assert_eq!(
+            modelfile.system.as_deref(),
+            Some("You are Ember.\nReply in no more than two sentences.\n")
+        );
+        assert_eq!(
+            modelfile.parameters,
+            [("temperature".to_string(), "0.9".to_string())]
+        );
+        assert!(modelfile.other.is_empty());
+    }
+
+    #[test]
+    fn parse_server_modelfile() {
+        let modelfile: Modelfile = r#"# Modelfile generated by "ollama show"
+FROM /usr/share/ollama/.ollama/models/blobs/sha256-e8ad
+TEMPLATE """{{ .System }}
+{{ .Prompt }}"""
+SYSTEM "You are Ember."
+PARAMETER stop <end_of_turn>
+PARAMETER temperature 0.90
+PARAMETER top_k 64
+LICENSE """Gemma Terms of Use"""
+"#
+        .parse()
+        .unwrap();
+
+        assert_eq!(modelfile.system.as_deref(), Some("You are Ember."));
+        assert_eq!(modelfile.other, ["TEMPLATE", "LICENSE"]);
+        assert_eq!(modelfile.parameters.len(), 3);
+    }
+
+    #[test]
+    fn compare_with_server() {
+        let local: Modelfile = MODELFILE.parse().unwrap();
+        let mut server = Modelfile {
+            from: "/blobs/sha256-e8ad".to_string(),
+            system: local.system.clone(),
+            parameters: vec![
+                ("temperature".to_string(), "0.90".to_string()),
+                ("top_k".to_string(), "64".to_string()),
+            ],
+            other: Vec::new(),
+        };
+        assert!(!local.differs_from(&server));
+
+        server.parameters[0].1 = "1.0".to_string();
+        assert!(local.differs_from(&server));
+
+        server.parameters[0].1 = "0.9".to_string();
+        server.system = Some("You are Mia.".to_string());
+        assert!(local.differs_from(&server));
+    }
+
+    #[test]
+    fn options() {
+        let modelfile: Modelfile =
+            "FROM gemma3\nPARAMETER temperature 0.9\nPARAMETER stop a\nPARAMETER stop \"b\""
+                .parse()
+                .unwrap();
+        let options = serde_json::to_value(modelfile.options().unwrap()).unwrap();
+        assert_eq!(
+            options,
+            serde_json::json!({ "temperature": 0.9_f32, "stop": ["a", "b"] })
+        );
+    }
+
+    #[test]
+    fn find_latest() {
+        let models = [LocalModel {
+            name: "ember:latest".to_string(),
+            modified_at: String::new(),
+            size: 0,
+        }];
+        assert!(find(&models, "ember").is_some());
+        assert!(find(&models, "ember:latest").is_some());
+        assert!(find(&models, "ember:v2").is_none());
+        assert!(find(&models, "mia").is_none());
+    }
+}
@@ -0,0 +1,226 @@
+//! Checks the characters' replies and choices before they are shown or printed, as the kiosk runs
+//! in public with children around.
+//!
+//! Replies are checked against a wordlist and patterns, then optionally a safety classifier model.
+//! Flagged replies are regenerated, and replaced if they are still flagged.
+
+use crate::{character::Character, conversation::VnOutput};
+use jiff::Timestamp;
+use log::{debug, warn};
+use ollama_rs::{
+    generation::chat::{request::ChatMessageRequest, ChatMessage},
+    Ollama,
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// The checks used when no moderation file is given.
+const DEFAULT_MODERATION: &str = include_str!("../extra/moderation.toml");
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModerationConfig {
+    #[serde(default)]
+    words: Vec<String>,
+    #[serde(default)]
+    patterns: Vec<String>,
+    classifier_model: Option<String>,
+    #[serde(default)]
+    regenerations: u8,
+    replacement: String,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Moderator {
+    /// Each pattern, including one matching any of the words
+    patterns: Vec<Regex>,
+    pub classifier_model: Option<String>,
+    /// Times a flagged reply is regenerated before it is replaced
+    pub regenerations: u8,
+    replacement: String,
+}
+
+impl Default for Moderator {
+    fn default() -> Self {
+        Self::parse(DEFAULT_MODERATION).expect("Default moderation should be valid")
+    }
+}
+
+impl Moderator {
+    pub(crate) fn load(path: &Path) -> Self {
+        let content = std::fs::read_to_string(path).expect("Failed to read moderation file");
+        Self::parse(&content).expect("Failed to parse moderation file")
+    }
+
+    fn parse(s: &str) -> anyhow::Result<Self> {
+        let config: ModerationConfig = toml::from_str(s)?;
+        debug!("Loaded moderation: {config:#?}");
+
+        let mut patterns = config
+            .patterns
+            .iter()
+            .map(|p| Regex::new(p))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if !config.words.is_empty() {
+            let words: Vec<String> = config.words.iter().map(|w| regex::escape(w)).collect();
+            patterns.push(Regex::new(&format!(r"(?i)\b(?:{})\b", words.join("|")))?);
+        }
+
+        Ok(Self {
+            patterns,
+            classifier_model: config.classifier_model,
+            regenerations: config.regenerations,
+            replacement: config.replacement,
+        })
+    }
+
+    /// Returns why the output is flagged, if it is.
+    pub(crate) async fn check(&self, ollama: &Ollama, output: &VnOutput) -> Option<String> {
+        let texts: Vec<&str> = std::iter::once(&output.response)
+            .chain(&output.user_replies)
+            .chain(&output.farewell)
+            .map(|t| t.as_str())
+            .collect();
+
+        for text in &texts {
+            if let Some(reason) = self.check_patterns(text) {
+                return Some(reason);
+            }
+        }
+
+        match &self.classifier_model {
+            Some(model) => classify(ollama, model, &texts.join("\n")).await,
+            None => None,
+        }
+    }
+
+    fn check_patterns(&self, text: &str) -> Option<String> {
+        self.patterns
+            .iter()
+            .find_map(|p| p.find(text))
+            .map(|m| format!("matched {:?}", m.as_str()))
+    }
+
+    /// A safe reply from the character, used in place of one that is still flagged.
+    pub(crate) fn replacement(&self, character: &Character) -> VnOutput {
+        VnOutput {
+            response: self.replacement.clone(),
+            ..character.starting_phrases()
+        }
+    }
+}
+
+/// Asks the classifier model whether the text is safe, failing closed if it cannot be asked.
+async fn classify(ollama: &Ollama, model: &str, text: &str) -> Option<String> {
+    let request =
+        ChatMessageRequest::new(model.to_string(), vec![ChatMessage::user(text.to_string())]);
+
+    match ollama.send_chat_messages(request).await {
+        Ok(response) => {
+            let verdict = response.message.content.trim();
+            verdict
+                .to_ascii_lowercase()
+                .starts_with("unsafe")
+                .then(|| format!("classifier: {}", verdict.replace('\n', " ")))
+        }
+        Err(e) => {
+            warn!("Failed to classify reply with {model}: {e}");
+            Some(format!("classifier failed: {e}"))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Action {
+    Regenerated,
+    Replaced,
+}
+
+/// A flagged reply, recorded in the conversation for later review.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Incident {
+    pub at: Timestamp,
+    pub output: VnOutput,
+    pub reason: String,
+    pub action: Action,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(response: &str) -> VnOutput {
+        VnOutput {
+            response: response.to_string(),
+            user_replies: vec!["Hi".to_string(), "Hello".to_string(), "Bye".to_string()],
+            end_conversation: false,
+            farewell: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn default_moderation() {
+        let moderator = Moderator::default();
+        let ollama = Ollama::default();
+        assert!(moderator.classifier_model.is_none());
+
+        assert_eq!(
+            moderator.check(&ollama, &output("Hello there!")).await,
+            None
+        );
+        assert_eq!(
+            moderator.check(&ollama, &output("Oh SHIT.")).await,
+            Some("matched \"SHIT\"".to_string())
+        );
+        assert_eq!(
+            moderator
+                .check(&ollama, &output("See https://example.com"))
+                .await,
+            Some("matched \"https://example.com\"".to_string())
+        );
+    }
+
+    #[test]
+    fn words_are_whole_words() {
+        let moderator = Moderator::parse(
+            r#"
+            words = ["hell"]
+            replacement = "..."
+            "#,
+        )
+        .unwrap();
+        assert!(moderator.check_patterns("What the HELL?").is_some());
+        assert!(moderator.check_patterns("Hello, shell").is_none());
+    }
+
+    #[tokio::test]
+    async fn choices_and_farewell_are_checked() {
+        let mut output = output("Hello");
+        output.user_replies[2] = "Call 0123 456 7890".to_string();
+        assert!(Moderator::default()
+            .check(&Ollama::default(), &output)
+            .await
+            .is_some());
+
+        let mut output = self::output("Hello");
+        output.farewell = Some("Bye, bitch".to_string());
+        assert!(Moderator::default()
+            .check(&Ollama::default(), &output)
+            .await
+            .is_some());
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        assert!(Moderator::parse(
+            r#"
+            patterns = ["("]
+            replacement = "..."
+            "#
+        )
+        .is_err());
+    }
+}
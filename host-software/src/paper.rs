@@ -0,0 +1,309 @@
+//! Estimates how much receipt paper has been used, so the roll can be changed before it runs out.
+
+use crate::driver::PrinterConnection;
+use escpos::{driver::Driver, errors::Result};
+use jiff::Timestamp;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// Height of a line of text at normal size, i.e. the printer's default line spacing of 1/6"
+const LINE_HEIGHT_MM: f32 = 25.4 / 6.0;
+
+/// Resolution of the printer, for measuring images
+const DOTS_PER_MM: f32 = 8.0;
+
+/// Modules across a typical QR code, including the quiet zone, as the data is not decoded
+const QR_CODE_MODULES: f32 = 29.0;
+
+/// Default size of a QR code module, in dots
+const DEFAULT_QR_MODULE_SIZE: u8 = 3;
+
+/// Paper fed out past the cutter when a receipt is cut
+const CUT_FEED_MM: f32 = 15.0;
+
+const ESC: u8 = 0x1b;
+const GS: u8 = 0x1d;
+
+#[derive(Debug)]
+struct Meter {
+    /// Height multiplier of the currently selected text size
+    text_height: u8,
+    /// Size of QR code modules, in dots
+    qr_module_size: u8,
+    /// Paper used since last taken
+    used_mm: f32,
+}
+
+impl Meter {
+    /// Measures how far the paper will be advanced by a chunk of ESC/POS commands.
+    ///
+    /// Only the commands that move the paper or change the line height are recognised, text
+    /// never contains control characters so anything else can be skipped over. Image data may
+    /// contain anything, so images are skipped over as a whole.
+    fn measure(&mut self, data: &[u8]) {
+        let mut i = 0;
+        while i < data.len() {
+            match data[i..] {
+                // Print and feed n lines
+                [ESC, b'd', n, ..] => {
+                    self.used_mm += f32::from(n) * f32::from(self.text_height) * LINE_HEIGHT_MM;
+                    i += 3;
+                }
+                // Initialise
+                [ESC, b'@', ..] => {
+                    self.text_height = 1;
+                    self.qr_module_size = DEFAULT_QR_MODULE_SIZE;
+                    i += 2;
+                }
+                // Select character size
+                [GS, b'!', n, ..] => {
+                    self.text_height = (n & 0x0f) + 1;
+                    i += 3;
+                }
+                // Print raster image
+                [GS, b'v', b'0', m, xl, xh, yl, yh, ..] => {
+                    let width_bytes = usize::from(u16::from_le_bytes([xl, xh]));
+                    let height = u16::from_le_bytes([yl, yh]);
+                    let scale = if m & 0x02 != 0 { 2.0 } else { 1.0 };
+                    self.used_mm += f32::from(height) * scale / DOTS_PER_MM;
+                    i += 8 + width_bytes * usize::from(height);
+                }
+                // 2D codes, with a length prefixed function
+                [GS, b'(', b'k', pl, ph, ..] => {
+                    let len = usize::from(u16::from_le_bytes([pl, ph]));
+                    let function = &data[(i + 5).min(data.len())..(i + 5 + len).min(data.len())];
+                    match function {
+                        // Set QR code module size
+                        [49, 67, n] => self.qr_module_size = *n,
+                        // Print QR code
+                        [49, 81, ..] => {
+                            self.used_mm +=
+                                QR_CODE_MODULES * f32::from(self.qr_module_size) / DOTS_PER_MM;
+                        }
+                        _ => {}
+                    }
+                    i += 5 + len;
+                }
+                // Cut
+                [GS, b'V', ..] => {
+                    self.used_mm += CUT_FEED_MM;
+                    i += 2;
+                }
+                _ => i += 1,
+            }
+        }
+    }
+}
+
+/// Driver that measures how far the paper is advanced by the commands sent through it.
+#[derive(Clone)]
+pub(crate) struct MeteredDriver<D> {
+    inner: D,
+    meter: Arc<Mutex<Meter>>,
+}
+
+impl<D> MeteredDriver<D> {
+    pub(crate) fn new(inner: D) -> Self {
+        Self {
+            inner,
+            meter: Arc::new(Mutex::new(Meter {
+                text_height: 1,
+                qr_module_size: DEFAULT_QR_MODULE_SIZE,
+                used_mm: 0.0,
+            })),
+        }
+    }
+
+    /// Returns the length of paper used since this was last called.
+    pub(crate) fn take_used_mm(&self) -> f32 {
+        std::mem::take(&mut self.meter.lock().unwrap().used_mm)
+    }
+}
+
+impl<D: Driver> Driver for MeteredDriver<D> {
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn write(&self, data: &[u8]) -> Result<()> {
+        self.meter.lock().unwrap().measure(data);
+        self.inner.write(data)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        self.inner.read(buf)
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<D: PrinterConnection> PrinterConnection for MeteredDriver<D> {
+    fn reports_status(&self) -> bool {
+        self.inner.reports_status()
+    }
+}
+
+/// Paper used from the roll currently loaded in a printer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct PaperRoll {
+    pub loaded_at: Timestamp,
+    pub used_mm: f32,
+}
+
+impl PaperRoll {
+    fn new() -> Self {
+        Self {
+            loaded_at: Timestamp::now(),
+            used_mm: 0.0,
+        }
+    }
+}
+
+/// Keeps a running total of the paper used from a printer's roll, saved so it survives restarts.
+pub(crate) struct PaperTracker {
+    path: PathBuf,
+    /// Length of paper on a new roll
+    pub roll_length_mm: f32,
+    /// Remaining length below which the roll should be changed soon
+    pub low_threshold_mm: f32,
+}
+
+impl PaperTracker {
+    pub(crate) fn new(path: PathBuf, roll_length_m: f32, low_threshold_m: f32) -> Self {
+        Self {
+            path,
+            roll_length_mm: roll_length_m * 1000.0,
+            low_threshold_mm: low_threshold_m * 1000.0,
+        }
+    }
+
+    /// File the paper used by a station's printer is saved in.
+    pub(crate) fn roll_file(state_directory: &Path, station: &str) -> PathBuf {
+        state_directory.join(format!("paper-{station}.json"))
+    }
+
+    /// Reads the saved roll, which may have been replaced since it was last read.
+    pub(crate) fn roll(&self) -> PaperRoll {
+        let roll = std::fs::read_to_string(&self.path)
+            .map_err(anyhow::Error::from)
+            .and_then(|s| Ok(serde_json::from_str(&s)?));
+
+        roll.unwrap_or_else(|e| {
+            warn!(
+                "Failed to read paper usage from {}, assuming a new roll: {e}",
+                self.path.display()
+            );
+            let roll = PaperRoll::new();
+            save(&self.path, &roll);
+            roll
+        })
+    }
+
+    pub(crate) fn remaining_mm(&self, roll: &PaperRoll) -> f32 {
+        (self.roll_length_mm - roll.used_mm).max(0.0)
+    }
+
+    pub(crate) fn is_low(&self, roll: &PaperRoll) -> bool {
+        self.remaining_mm(roll) < self.low_threshold_mm
+    }
+
+    /// Adds paper used by a print job to the running total.
+    pub(crate) fn record(&self, used_mm: f32) -> PaperRoll {
+        let mut roll = self.roll();
+        roll.used_mm += used_mm;
+        save(&self.path, &roll);
+        roll
+    }
+
+    pub(crate) fn new_roll(&self) {
+        new_roll(&self.path);
+    }
+}
+
+/// Resets the running total of paper used, for when a fresh roll has been loaded.
+pub(crate) fn new_roll(path: &Path) {
+    info!("New paper roll loaded, resetting {}", path.display());
+    save(path, &PaperRoll::new());
+}
+
+fn save(path: &Path, roll: &PaperRoll) {
+    let result = serde_json::to_string_pretty(roll)
+        .map_err(anyhow::Error::from)
+        .and_then(|s| Ok(std::fs::write(path, s)?));
+
+    if let Err(e) = result {
+        warn!("Failed to save paper usage to {}: {e}", path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measure(data: &[u8]) -> f32 {
+        let mut meter = Meter {
+            text_height: 1,
+            qr_module_size: DEFAULT_QR_MODULE_SIZE,
+            used_mm: 0.0,
+        };
+        meter.measure(data);
+        meter.used_mm
+    }
+
+    #[test]
+    fn text_is_not_measured() {
+        assert_eq!(measure(b"hello, world"), 0.0);
+    }
+
+    #[test]
+    fn line_feeds() {
+        assert_eq!(measure(&[ESC, b'd', 1]), LINE_HEIGHT_MM);
+        assert_eq!(measure(b"\x1bd\x03abc\x1bd\x01"), 4.0 * LINE_HEIGHT_MM);
+    }
+
+    #[test]
+    fn text_size() {
+        // Double height then back to normal
+        assert_eq!(
+            measure(b"\x1d!\x11big\x1bd\x01\x1d!\x00small\x1bd\x01"),
+            3.0 * LINE_HEIGHT_MM
+        );
+    }
+
+    #[test]
+    fn init_resets_text_size() {
+        assert_eq!(measure(b"\x1d!\x11\x1b@\x1bd\x01"), LINE_HEIGHT_MM);
+    }
+
+    #[test]
+    fn raster_image() {
+        // 16 dots high, with data that looks like a line feed
+        let mut data = vec![GS, b'v', b'0', 0, 1, 0, 16, 0];
+        data.extend([ESC, b'd', 1].repeat(5));
+        data.push(0);
+        assert_eq!(measure(&data), 2.0);
+
+        // Double height
+        assert_eq!(measure(&[GS, b'v', b'0', 2, 0, 0, 8, 0]), 2.0);
+    }
+
+    #[test]
+    fn qr_code() {
+        // Module size, then data that looks like a line feed, then print
+        let mut data = vec![GS, b'(', b'k', 3, 0, 49, 67, 8];
+        data.extend([GS, b'(', b'k', 6, 0, 49, 80, 48, ESC, b'd', 9]);
+        data.extend([GS, b'(', b'k', 3, 0, 49, 81, 48]);
+        assert_eq!(measure(&data), QR_CODE_MODULES);
+    }
+
+    #[test]
+    fn cut() {
+        assert_eq!(measure(&[GS, b'V', b'A', 0]), CUT_FEED_MM);
+    }
+}
@@ -0,0 +1,125 @@
+//! Renders controller screens to image files, without needing a controller attached.
+
+use crate::character::CharacterCollection;
+use clap::{Args, Subcommand};
+use embedded_graphics::{pixelcolor::Rgb666, prelude::Size};
+use embedded_graphics_simulator::{OutputSettingsBuilder, SimulatorDisplay};
+use icd::Screen;
+use log::info;
+use std::path::PathBuf;
+
+#[derive(Debug, Args)]
+pub(crate) struct PreviewArgs {
+    /// PNG file to write the rendered screen to
+    #[arg(long, short)]
+    output: PathBuf,
+
+    /// Factor to scale the rendered screen up by
+    #[arg(long, default_value = "1")]
+    scale: u32,
+
+    #[command(subcommand)]
+    screen: PreviewScreen,
+}
+
+#[derive(Debug, Subcommand)]
+enum PreviewScreen {
+    /// Character select screen, with the character in the middle
+    CharacterSelect(CharacterArgs),
+
+    /// Three of the character's opening lines, picked at random
+    Choices(CharacterArgs),
+
+    /// A reply from the character, followed by three of their opening lines
+    Reply {
+        #[command(flatten)]
+        character: CharacterArgs,
+
+        /// Text of the reply
+        #[arg(long)]
+        response: String,
+    },
+
+    /// Any screen, read from a JSON file
+    Json {
+        /// File containing a serialised `icd::Screen`
+        file: PathBuf,
+    },
+}
+
+#[derive(Debug, Args)]
+struct CharacterArgs {
+    /// File containing character definitions
+    #[arg(long, env)]
+    character_file: PathBuf,
+
+    /// Name of the character to show, defaults to the first character in the file
+    #[arg(long)]
+    character: Option<String>,
+}
+
+impl CharacterArgs {
+    fn load(&self) -> (CharacterCollection, usize) {
+        let characters = CharacterCollection::load(&self.character_file);
+
+        let idx = match &self.character {
+            Some(name) => characters
+                .characters
+                .iter()
+                .position(|c| &c.name == name)
+                .unwrap_or_else(|| panic!("No character named \"{name}\"")),
+            None => 0,
+        };
+
+        (characters, idx)
+    }
+}
+
+impl PreviewScreen {
+    fn build(&self) -> Screen {
+        match self {
+            Self::CharacterSelect(args) => {
+                let (characters, idx) = args.load();
+                Screen::CharacterSelect(characters.select_screen(idx))
+            }
+            Self::Choices(args) => {
+                let (characters, idx) = args.load();
+                let character = &characters.characters[idx];
+                Screen::Choices(character.choice_screen(&character.starting_phrases()))
+            }
+            Self::Reply {
+                character: args,
+                response,
+            } => {
+                let (characters, idx) = args.load();
+                let character = &characters.characters[idx];
+                let mut output = character.starting_phrases();
+                output.response = response.clone();
+                Screen::Reply(character.reply_screen(&output))
+            }
+            Self::Json { file } => {
+                let content = std::fs::read_to_string(file).expect("Failed to read screen file");
+                serde_json::from_str(&content).expect("Failed to parse screen file")
+            }
+        }
+    }
+}
+
+pub(crate) fn run(args: PreviewArgs) {
+    let screen = args.screen.build();
+
+    let mut display =
+        SimulatorDisplay::<Rgb666>::new(Size::new(screens::WIDTH.into(), screens::HEIGHT.into()));
+    screens::draw_screen(&screen, &mut display).unwrap();
+
+    let report = screens::report(&screen, screens::screen_box());
+    for (i, fit) in report.text.iter().enumerate() {
+        println!("Text block {}: {fit:?}", i + 1);
+    }
+
+    display
+        .to_rgb_output_image(&OutputSettingsBuilder::new().scale(args.scale).build())
+        .save_png(&args.output)
+        .expect("Failed to save rendered screen");
+    info!("Rendered {:?} to {}", screen.kind(), args.output.display());
+}
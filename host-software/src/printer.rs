@@ -1,63 +1,349 @@
-use crate::Character;
+use crate::{
+    character::{Availability, Character},
+    driver::PrinterConnection,
+    health::HealthReport,
+    memory::VisitorId,
+    paper::{MeteredDriver, PaperTracker},
+    template::{substitute, Block, ReceiptTemplate, Section},
+    text::{transliterate, Charset},
+    warmup::ModelLoad,
+};
 use escpos::{
     driver::Driver,
-    errors::Result,
+    errors::{PrinterError, Result},
     printer_options::PrinterOptions,
-    ui::line::{LineBuilder, LineStyle},
-    utils::{JustifyMode, Protocol, UnderlineMode},
+    ui::line::LineBuilder,
+    utils::{
+        JustifyMode, PageCode, Protocol, QRCodeCorrectionLevel, QRCodeModel, QRCodeOption,
+        RealTimeStatusRequest, RealTimeStatusResponse, UnderlineMode,
+    },
 };
-use log::info;
+use icd::DeviceInfo;
+use log::{debug, info, warn};
+use std::collections::HashMap;
 use text_splitter::TextSplitter;
 
+/// Page code selected on the printer, text is converted to the matching character set.
+const PAGE_CODE: PageCode = PageCode::PC858;
+const CHARSET: Charset = Charset::Cp858;
+
 trait PrinterExt {
+    /// Writes a line of arbitrary text, converting it to the printer's character set.
+    fn writeln_text(&mut self, s: &str) -> Result<&mut Self>;
+
+    /// Writes arbitrary text wrapped to the width of the paper at the current text size,
+    /// converting it to the printer's character set.
     fn write_multiline(&mut self, s: &str) -> Result<&mut Self>;
+
+    /// Prints blocks from a receipt template, filling in their placeholders.
+    fn print_blocks(&mut self, blocks: &[Block], values: &[(&str, &str)]) -> Result<&mut Self>;
 }
 
 impl<D: Driver> PrinterExt for escpos::printer::Printer<D> {
+    fn writeln_text(&mut self, s: &str) -> Result<&mut Self> {
+        self.writeln(&transliterate(s, CHARSET))
+    }
+
     fn write_multiline(&mut self, s: &str) -> Result<&mut Self> {
-        let splitter = TextSplitter::new(self.options().get_characters_per_line() as usize);
+        let width = self.options().get_characters_per_line() / self.style_state().text_size.0;
+        let splitter = TextSplitter::new(usize::from(width.max(1)));
 
-        for s in splitter.chunks(s) {
+        for s in splitter.chunks(&transliterate(s, CHARSET)) {
             self.writeln(s)?;
         }
 
         Ok(self)
     }
+
+    fn print_blocks(&mut self, blocks: &[Block], values: &[(&str, &str)]) -> Result<&mut Self> {
+        for block in blocks {
+            match block {
+                Block::Text {
+                    text,
+                    justify,
+                    size: (width, height),
+                    bold,
+                    underline,
+                    invert,
+                } => {
+                    self.justify((*justify).into())?
+                        .size(*width, *height)?
+                        .bold(*bold)?
+                        .underline(match underline {
+                            true => UnderlineMode::Single,
+                            false => UnderlineMode::None,
+                        })?
+                        .reverse(*invert)?;
+
+                    for line in substitute(text, values).lines() {
+                        match line.is_empty() {
+                            true => self.feed()?,
+                            false => self.write_multiline(line)?,
+                        };
+                    }
+
+                    self.size(1, 1)?
+                        .bold(false)?
+                        .underline(UnderlineMode::None)?
+                        .reverse(false)?;
+                }
+                Block::Separator { style } => {
+                    self.draw_line(LineBuilder::new().style((*style).into()).build())?;
+                }
+                Block::Feed { lines } => {
+                    self.feeds(*lines)?;
+                }
+                Block::QrCode {
+                    data,
+                    justify,
+                    size,
+                } => {
+                    self.justify((*justify).into())?.qrcode_option(
+                        &substitute(data, values),
+                        QRCodeOption::new(QRCodeModel::Model2, *size, QRCodeCorrectionLevel::M),
+                    )?;
+                }
+                Block::Image { path, justify } => {
+                    self.justify((*justify).into())?
+                        .bit_image(&path.to_string_lossy())?;
+                }
+            }
+        }
+
+        Ok(self)
+    }
+}
+
+/// State of the printer, as reported by its real-time status (DLE EOT).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PrinterStatus {
+    /// The printer answered the status request
+    pub responding: bool,
+    pub online: bool,
+    pub cover_open: bool,
+    pub paper_out: bool,
+    pub paper_low: bool,
+    pub error: bool,
+}
+
+impl PrinterStatus {
+    pub(crate) const NOT_RESPONDING: Self = Self {
+        responding: false,
+        online: false,
+        cover_open: false,
+        paper_out: false,
+        paper_low: false,
+        error: false,
+    };
+
+    /// Used when the printer cannot be asked, e.g. when printing to a file
+    const ASSUMED_READY: Self = Self {
+        responding: true,
+        online: true,
+        ..Self::NOT_RESPONDING
+    };
+
+    /// Decodes the responses to the printer, offline cause and roll paper sensor status requests.
+    fn parse(printer: u8, offline_cause: u8, roll_paper: u8) -> Result<Self> {
+        let printer = RealTimeStatusResponse::parse(RealTimeStatusRequest::Printer, printer)?;
+        let offline_cause =
+            RealTimeStatusResponse::parse(RealTimeStatusRequest::OfflineCause, offline_cause)?;
+        let roll_paper =
+            RealTimeStatusResponse::parse(RealTimeStatusRequest::RollPaperSensor, roll_paper)?;
+
+        let flag = |status: &HashMap<RealTimeStatusResponse, bool>, flag| status[&flag];
+
+        Ok(Self {
+            responding: true,
+            online: flag(&printer, RealTimeStatusResponse::Online),
+            cover_open: !flag(&offline_cause, RealTimeStatusResponse::CoverClosed),
+            paper_out: flag(
+                &offline_cause,
+                RealTimeStatusResponse::PrintingStopsDueToPaperEnd,
+            ) || !flag(
+                &roll_paper,
+                RealTimeStatusResponse::RollPaperEndSensorPaperPresent,
+            ),
+            paper_low: !flag(
+                &roll_paper,
+                RealTimeStatusResponse::RollPaperNearEndSensorPaperAdequate,
+            ),
+            error: flag(&offline_cause, RealTimeStatusResponse::ErrorOccurred),
+        })
+    }
+
+    /// Describes what is stopping the printer from printing, if anything.
+    pub(crate) fn problem(&self) -> Option<&'static str> {
+        if !self.responding {
+            Some("The printer is not responding, check it is switched on and connected.")
+        } else if self.paper_out {
+            Some("The printer is out of paper, load a new roll.")
+        } else if self.cover_open {
+            Some("The printer cover is open, close it.")
+        } else if self.error {
+            Some("The printer has an error, check it over and power cycle it.")
+        } else if !self.online {
+            Some("The printer is offline.")
+        } else {
+            None
+        }
+    }
 }
 
 pub(crate) struct Printer<D: Driver> {
-    printer: escpos::printer::Printer<D>,
+    printer: escpos::printer::Printer<MeteredDriver<D>>,
+    /// Handle to the same connection as `printer`, used to read status responses and measure the
+    /// paper used
+    driver: MeteredDriver<D>,
+    paper: PaperTracker,
+    template: ReceiptTemplate,
+    /// Name of the station the printer belongs to
+    station: String,
 }
 
-impl<D: Driver> Printer<D> {
-    pub(crate) fn new(driver: D) -> Self {
+impl<D: PrinterConnection> Printer<D> {
+    /// Sets up the printer and prints a startup message, which fails if the printer is not
+    /// responding.
+    pub(crate) fn new(
+        driver: D,
+        paper: PaperTracker,
+        template: ReceiptTemplate,
+        station: &str,
+    ) -> anyhow::Result<Self> {
+        let driver = MeteredDriver::new(driver);
+
         let mut printer = escpos::printer::Printer::new(
-            driver,
+            driver.clone(),
             Protocol::default(),
             Some(PrinterOptions::default()),
         );
 
         info!("Initialise printer");
-        printer.init().unwrap();
+        printer.init()?.page_code(PAGE_CODE)?;
 
         let now = jiff::Zoned::now();
         printer
-            .writeln(&format!("{now:.0}"))
-            .unwrap()
-            .writeln("llm-vn-host starting...")
-            .unwrap()
-            .feed()
-            .unwrap()
-            .print()
-            .unwrap();
+            .writeln(&format!("{now:.0}"))?
+            .writeln("llm-vn-host starting...")?
+            .feed()?
+            .print()?;
+
+        let mut printer = Self {
+            printer,
+            driver,
+            paper,
+            template,
+            station: station.to_string(),
+        };
+        printer.finish_job("Startup message");
+        Ok(printer)
+    }
+
+    /// Queries the printer's real-time status, if the connection can report it.
+    ///
+    /// Waiting for each response blocks until the connection times out, so the query runs on a
+    /// blocking thread rather than holding up the other stations.
+    pub(crate) async fn status(&self) -> PrinterStatus {
+        if !self.driver.reports_status() {
+            return PrinterStatus::ASSUMED_READY;
+        }
 
-        Self { printer }
+        let mut printer = self.printer.clone();
+        let driver = self.driver.clone();
+        let result = tokio::task::spawn_blocking(move || query_status(&mut printer, &driver)).await;
+
+        match result {
+            Ok(Ok(status)) => status,
+            Ok(Err(e)) => {
+                warn!("Failed to query printer status: {e}");
+                PrinterStatus::NOT_RESPONDING
+            }
+            Err(e) => {
+                warn!("Printer status query failed: {e}");
+                PrinterStatus::NOT_RESPONDING
+            }
+        }
+    }
+}
+
+fn query_status<D: Driver>(
+    printer: &mut escpos::printer::Printer<D>,
+    driver: &D,
+) -> Result<PrinterStatus> {
+    let mut query = |request| -> Result<u8> {
+        printer.real_time_status(request)?.send_status()?;
+
+        let mut buf = [0; 1];
+        match driver.read(&mut buf)? {
+            1 => Ok(buf[0]),
+            _ => Err(PrinterError::Io("no status response".to_string())),
+        }
+    };
+
+    PrinterStatus::parse(
+        query(RealTimeStatusRequest::Printer)?,
+        query(RealTimeStatusRequest::OfflineCause)?,
+        query(RealTimeStatusRequest::RollPaperSensor)?,
+    )
+}
+
+impl<D: Driver> Printer<D> {
+    /// Starts counting paper used from a fresh roll.
+    pub(crate) fn new_roll_loaded(&mut self) {
+        self.paper.new_roll();
+    }
+
+    /// Adds the paper used since the last job to the running total for the roll.
+    fn finish_job(&mut self, job: &str) {
+        let used_mm = self.driver.take_used_mm();
+        let roll = self.paper.record(used_mm);
+        let remaining_m = self.paper.remaining_mm(&roll) / 1000.0;
+
+        debug!("{job} used ~{used_mm:.0} mm of paper, ~{remaining_m:.1} m left on the roll");
+        if self.paper.is_low(&roll) {
+            warn!("Paper roll is running low, ~{remaining_m:.1} m left");
+        }
+    }
+
+    /// Prints one section of the receipt template, styled for the character being chatted with.
+    ///
+    /// `extra` fills in the placeholders specific to the section.
+    fn print_template(
+        &mut self,
+        section: Section,
+        character: &Character,
+        extra: &[(&str, &str)],
+    ) -> Result<()> {
+        let now = jiff::Zoned::now();
+        let time = now.strftime("%H:%M:%S").to_string();
+        let date = now.strftime("%Y-%m-%d").to_string();
+
+        let mut values = vec![
+            ("time", time.as_str()),
+            ("date", date.as_str()),
+            ("station", self.station.as_str()),
+            ("character.name", character.name.as_str()),
+            ("character.description", character.description.as_str()),
+        ];
+        values.extend_from_slice(extra);
+
+        let blocks = character
+            .receipt
+            .apply(section, self.template.section(section));
+        self.printer
+            .smoothing(true)?
+            .print_blocks(&blocks, &values)?;
+
+        Ok(())
     }
 
     pub(crate) fn print_ready(
         &mut self,
         characters: &[Character],
         ollama_model_names: &[&str],
+        model_loads: &[ModelLoad],
+        health: &HealthReport,
+        controller: &DeviceInfo,
     ) -> Result<()> {
         let now = jiff::Zoned::now();
 
@@ -73,14 +359,45 @@ impl<D: Driver> Printer<D> {
         self.printer
             .writeln(&format!("{now:.0}"))?
             .writeln("llm-vn-host")?
+            .writeln_text(&format!("Station: {}", self.station))?
             .feed()?;
 
+        // Print controller details
+        self.printer
+            .writeln("Controller:")?
+            .writeln(&format!(
+                " - firmware: {} ({})",
+                controller.firmware_version, controller.git_hash
+            ))?
+            .writeln(&format!(" - ICD: {:016x}", controller.schema_hash))?
+            .feed()?;
+
+        // Print health check results
+        self.printer.writeln("Health check:")?;
+        for check in &health.checks {
+            self.printer.write_multiline(&format!(
+                " - [{}] {}: {}",
+                check.status, check.name, check.detail
+            ))?;
+        }
+        self.printer.feed()?;
+
         // Print configured characters
         self.printer.writeln("Available characters:")?;
         for character in characters {
             self.printer
-                .writeln(&format!(" - name: {}", character.name))?
-                .writeln(&format!("   model: {}", character.model_name))?;
+                .writeln_text(&format!(" - name: {}", character.name))?
+                .writeln(&format!("   model: {}", character.primary_model()))?;
+            match character.availability() {
+                Availability::Available => {}
+                Availability::Fallback => {
+                    self.printer
+                        .writeln(&format!("   FALLBACK: {}", character.model()))?;
+                }
+                Availability::Unavailable => {
+                    self.printer.writeln("   UNAVAILABLE")?;
+                }
+            }
         }
         self.printer.feed()?;
 
@@ -91,106 +408,142 @@ impl<D: Driver> Printer<D> {
         }
         self.printer.feed()?;
 
+        // Print how long each model took to load
+        self.printer.writeln("Model load times:")?;
+        for load in model_loads {
+            match load.duration {
+                Some(duration) => self.printer.writeln(&format!(
+                    " - {}: {:.1} s",
+                    load.model,
+                    duration.as_secs_f32()
+                ))?,
+                None => self
+                    .printer
+                    .writeln(&format!(" - {}: failed to load", load.model))?,
+            };
+        }
+        self.printer.feed()?;
+
+        // Print estimated paper usage
+        let roll = self.paper.roll();
+        self.printer
+            .writeln("Paper (estimated):")?
+            .writeln(&format!(" - roll loaded: {:.0}", roll.loaded_at))?
+            .writeln(&format!(
+                " - used: {:.1} m of {:.0} m",
+                roll.used_mm / 1000.0,
+                self.paper.roll_length_mm / 1000.0
+            ))?;
+        if self.paper.is_low(&roll) {
+            self.printer
+                .bold(true)?
+                .writeln("PAPER LOW: load a new roll soon")?
+                .bold(false)?;
+        }
+        self.printer.feed()?;
+
         // Say we are ready
         self.printer.writeln("Ready!")?.print_cut()?;
+        self.finish_job("Ready message");
 
         Ok(())
     }
 
     pub(crate) fn print_chat_header(&mut self, character: &Character) -> Result<()> {
-        let line_style = LineBuilder::new().style(LineStyle::Simple).build();
-        let now = jiff::Zoned::now();
+        self.print_template(Section::Header, character, &[])?;
+        self.printer.print()?;
+        self.finish_job("Chat header");
 
-        self.printer
-            .size(1, 1)?
-            .bold(false)?
-            .underline(UnderlineMode::None)?
-            .justify(JustifyMode::CENTER)?
-            .writeln(&format!(
-                "{:02}:{:02}:{:02}",
-                now.hour(),
-                now.minute(),
-                now.second()
-            ))?
-            .feed()?
-            .writeln("Chat with")?
-            .size(2, 2)?
-            .bold(true)?
-            .underline(UnderlineMode::Single)?
-            .writeln(&character.name)?
-            .size(1, 1)?
-            .bold(false)?
-            .underline(UnderlineMode::None)?
-            .feed()?
-            .write_multiline(&character.description)?
-            .feed()?
-            .draw_line(line_style)?
-            .feed()?
-            .print()?;
+        Ok(())
+    }
+
+    pub(crate) fn print_user_message(&mut self, character: &Character, msg: &str) -> Result<()> {
+        self.print_template(
+            Section::UserMessage,
+            character,
+            &[("name", "You"), ("message", msg)],
+        )?;
+        self.printer.print()?;
+        self.finish_job("Message from visitor");
 
         Ok(())
     }
 
-    fn print_transcript_message(
+    pub(crate) fn print_character_message(
         &mut self,
-        justify: JustifyMode,
-        name: &str,
-        text: &str,
-        feeds: usize,
+        character: &Character,
+        msg: &str,
     ) -> Result<()> {
-        self.printer
-            .justify(justify)?
-            .bold(true)?
-            .underline(UnderlineMode::Single)?
-            .writeln(name)?
-            .bold(false)?
-            .underline(UnderlineMode::None)?
-            .write_multiline(text)?
-            .justify(JustifyMode::CENTER)?;
-
-        for _ in 0..feeds {
-            self.printer.writeln(".")?;
-        }
-
+        self.print_template(
+            Section::CharacterMessage,
+            character,
+            &[("name", &character.name), ("message", msg)],
+        )?;
         self.printer.print()?;
+        self.finish_job(&format!("Message from {}", character.name));
 
         Ok(())
     }
 
-    pub(crate) fn print_user_message(&mut self, msg: &str) -> Result<()> {
-        self.print_transcript_message(JustifyMode::LEFT, "You", msg, 1)
-    }
-
-    pub(crate) fn print_character_message(
+    pub(crate) fn print_chat_footer(
         &mut self,
         character: &Character,
-        msg: &str,
+        visitor: Option<&VisitorId>,
     ) -> Result<()> {
-        self.print_transcript_message(JustifyMode::RIGHT, &character.name, msg, 7)
+        self.print_template(Section::Footer, character, &[])?;
+        if let Some(visitor) = visitor {
+            self.print_template(
+                Section::Visitor,
+                character,
+                &[("visitor", &visitor.to_string())],
+            )?;
+        }
+        self.printer.print_cut()?;
+        self.finish_job("Chat footer");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_ready() {
+        let status = PrinterStatus::parse(0x12, 0x12, 0x12).unwrap();
+        assert!(status.online);
+        assert!(!status.paper_low);
+        assert_eq!(status.problem(), None);
     }
 
-    pub(crate) fn print_chat_footer(&mut self) -> Result<()> {
-        let line_style = LineBuilder::new().style(LineStyle::Simple).build();
+    #[test]
+    fn status_paper_low() {
+        let status = PrinterStatus::parse(0x12, 0x12, 0x1e).unwrap();
+        assert!(status.paper_low);
+        assert!(!status.paper_out);
+        assert_eq!(status.problem(), None);
+    }
 
-        self.printer
-            .justify(JustifyMode::CENTER)?
-            .bold(false)?
-            .underline(UnderlineMode::None)?
-            .size(1, 1)?
-            .draw_line(line_style)?
-            .feed()?
-            .write_multiline("This chat was with a large language model. It may not accurately represent reality or the views of individuals. Do not blindly believe everything it has told you.")?
-            .feed()?
-            .write_multiline("Feel free to keep this print out.")?
-            .feed()?
-            .underline(UnderlineMode::Single)?
-            .writeln("thelateshows.org.uk")?
-            .writeln("makerspace.org.uk")?
-            .writeln("github.com/DanNixon/llm-vn-lateshows25")?
-            .underline(UnderlineMode::None)?
-            .feed()?
-            .print_cut()?;
+    #[test]
+    fn status_paper_out() {
+        let status = PrinterStatus::parse(0x1a, 0x32, 0x7e).unwrap();
+        assert!(!status.online);
+        assert!(status.paper_out);
+        assert!(!status.cover_open);
+        assert!(status.problem().unwrap().contains("out of paper"));
+    }
 
-        Ok(())
+    #[test]
+    fn status_cover_open() {
+        let status = PrinterStatus::parse(0x1a, 0x16, 0x12).unwrap();
+        assert!(status.cover_open);
+        assert!(!status.paper_out);
+        assert!(status.problem().unwrap().contains("cover is open"));
+    }
+
+    #[test]
+    fn status_invalid_response() {
+        assert!(PrinterStatus::parse(0xff, 0x12, 0x12).is_err());
     }
 }
@@ -0,0 +1,69 @@
+//! Reads visitor IDs from a barcode scanner, which must be set up to send each code it scans as a
+//! line of text (e.g. in USB serial mode).
+
+use crate::memory::VisitorId;
+use log::{info, warn};
+use std::{
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+
+/// How long a scanned ID is held for the next conversation, so that it is not given to a
+/// different visitor later on
+const CLAIM_WINDOW: Duration = Duration::from_secs(120);
+
+/// Delay before trying to reopen a scanner that has gone away
+const REOPEN_DELAY: Duration = Duration::from_secs(5);
+
+pub(crate) struct Scanner {
+    scans: watch::Receiver<Option<(VisitorId, Instant)>>,
+}
+
+impl Scanner {
+    /// Starts reading from the scanner in the background.
+    pub(crate) fn spawn(path: PathBuf) -> Self {
+        let (tx, rx) = watch::channel(None);
+
+        std::thread::spawn(move || loop {
+            match std::fs::File::open(&path) {
+                Ok(file) => {
+                    info!("Reading visitor IDs from {}", path.display());
+                    for line in BufReader::new(file).lines() {
+                        let line = match line {
+                            Ok(line) => line,
+                            Err(e) => {
+                                warn!("Failed to read from scanner {}: {e}", path.display());
+                                break;
+                            }
+                        };
+
+                        match VisitorId::parse(&line) {
+                            Some(id) => {
+                                info!("Scanned visitor ID {id}");
+                                tx.send_replace(Some((id, Instant::now())));
+                            }
+                            None => warn!("Scanned something that is not a visitor ID: {line:?}"),
+                        }
+                    }
+                }
+                Err(e) => warn!("Failed to open scanner {}: {e}", path.display()),
+            }
+
+            std::thread::sleep(REOPEN_DELAY);
+        });
+
+        Self { scans: rx }
+    }
+
+    /// Takes the visitor ID scanned since the last conversation, if it was scanned recently.
+    pub(crate) fn take_claim(&mut self) -> Option<VisitorId> {
+        if !self.scans.has_changed().unwrap_or(false) {
+            return None;
+        }
+
+        let scan = self.scans.borrow_and_update().clone();
+        scan.and_then(|(id, at)| (at.elapsed() < CLAIM_WINDOW).then_some(id))
+    }
+}
@@ -0,0 +1,76 @@
+use crate::driver::PrinterUri;
+use log::debug;
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+/// A kiosk station, pairing a controller with the printer next to it.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Station {
+    /// Name of the station, used in logs, receipts and saved conversations
+    pub name: String,
+
+    /// USB serial number of the station's controller, the first controller found is used if not
+    /// set
+    #[serde(default)]
+    pub controller_serial: Option<String>,
+
+    /// URI of the station's thermal printer, see `driver.rs`, without one the controller shows
+    /// the whole conversation
+    #[serde(default)]
+    pub printer: Option<PrinterUri>,
+
+    /// Barcode scanner used by returning visitors to scan the visitor ID on their receipt
+    #[serde(default)]
+    pub visitor_scanner: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StationFile {
+    stations: Vec<Station>,
+}
+
+pub(crate) fn load(path: &Path) -> Vec<Station> {
+    let content = std::fs::read_to_string(path).expect("Failed to read station file");
+    let file: StationFile = toml::from_str(&content).expect("Failed to parse station file");
+    debug!("Loaded stations: {:#?}", file.stations);
+
+    assert!(
+        !file.stations.is_empty(),
+        "There must be at least one station defined."
+    );
+
+    if file.stations.len() > 1 {
+        assert!(
+            file.stations.iter().all(|s| s.controller_serial.is_some()),
+            "Every station must set controller_serial when there is more than one station."
+        );
+    }
+
+    let mut names = HashSet::new();
+    let mut serials = HashSet::new();
+    let mut printers = HashSet::new();
+    for station in &file.stations {
+        assert!(
+            names.insert(&station.name),
+            "Station name \"{}\" is used more than once.",
+            station.name
+        );
+        if let Some(serial) = &station.controller_serial {
+            assert!(
+                serials.insert(serial),
+                "Controller serial \"{serial}\" is used by more than one station."
+            );
+        }
+        if let Some(printer) = &station.printer {
+            assert!(
+                printers.insert(printer),
+                "Printer \"{printer}\" is used by more than one station."
+            );
+        }
+    }
+
+    file.stations
+}
@@ -0,0 +1,434 @@
+//! Layout of the printed conversation receipts, loaded from a TOML file so they can be rebranded
+//! without recompiling.
+//!
+//! Text may contain placeholders, which are replaced when printed:
+//!
+//! - `{time}`, `{date}`: when the block is printed
+//! - `{station}`: name of the station printing the receipt
+//! - `{character.name}`, `{character.description}`: the character being chatted with
+//! - `{name}`, `{message}`: who sent a message and what it said (messages only)
+//! - `{visitor}`: the visitor's ID (visitor section only)
+//!
+//! Each character may restyle parts of the template, see [`CharacterStyle`].
+
+use escpos::{ui::line::LineStyle, utils::JustifyMode};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// The layout used when no template file is given.
+const DEFAULT_TEMPLATE: &str = include_str!("../extra/receipt.toml");
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ReceiptTemplate {
+    /// Printed when a conversation starts
+    pub header: Vec<Block>,
+    /// Printed for each message sent by the visitor
+    pub user_message: Vec<Block>,
+    /// Printed for each reply from the character
+    pub character_message: Vec<Block>,
+    /// Printed when a conversation ends, the receipt is then cut
+    pub footer: Vec<Block>,
+    /// Printed after the footer when the character remembers visitors
+    #[serde(default)]
+    pub visitor: Vec<Block>,
+}
+
+impl Default for ReceiptTemplate {
+    fn default() -> Self {
+        let template: Self =
+            toml::from_str(DEFAULT_TEMPLATE).expect("Default receipt template should be valid");
+        template.validate();
+        template
+    }
+}
+
+impl ReceiptTemplate {
+    pub(crate) fn load(path: &Path) -> Self {
+        let content = std::fs::read_to_string(path).expect("Failed to read receipt template");
+        let mut template: Self =
+            toml::from_str(&content).expect("Failed to parse receipt template");
+
+        // Images are relative to the template
+        if let Some(dir) = path.parent() {
+            for block in template.blocks_mut() {
+                if let Block::Image { path, .. } = block {
+                    *path = dir.join(&*path);
+                }
+            }
+        }
+
+        template.validate();
+        debug!("Loaded receipt template: {template:#?}");
+        template
+    }
+
+    /// Checks for anything that would only fail once a receipt is being printed.
+    fn validate(&self) {
+        for block in self.blocks() {
+            match block {
+                Block::Text {
+                    size: (width, height),
+                    ..
+                } => assert!(
+                    (1..=8).contains(width) && (1..=8).contains(height),
+                    "Receipt template text size must be between 1 and 8, got [{width}, {height}]"
+                ),
+                Block::Image { path, .. } => assert!(
+                    path.is_file(),
+                    "Receipt template image {} does not exist",
+                    path.display()
+                ),
+                Block::QrCode { size, .. } => assert!(
+                    (1..=15).contains(size),
+                    "Receipt template QR code size must be between 1 and 15, got {size}"
+                ),
+                Block::Separator { .. } | Block::Feed { .. } => {}
+            }
+        }
+    }
+
+    pub(crate) fn section(&self, section: Section) -> &[Block] {
+        match section {
+            Section::Header => &self.header,
+            Section::UserMessage => &self.user_message,
+            Section::CharacterMessage => &self.character_message,
+            Section::Footer => &self.footer,
+            Section::Visitor => &self.visitor,
+        }
+    }
+
+    fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.header
+            .iter()
+            .chain(self.user_message.iter())
+            .chain(self.character_message.iter())
+            .chain(self.footer.iter())
+            .chain(self.visitor.iter())
+    }
+
+    fn blocks_mut(&mut self) -> impl Iterator<Item = &mut Block> {
+        self.header
+            .iter_mut()
+            .chain(self.user_message.iter_mut())
+            .chain(self.character_message.iter_mut())
+            .chain(self.footer.iter_mut())
+            .chain(self.visitor.iter_mut())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Section {
+    Header,
+    UserMessage,
+    CharacterMessage,
+    Footer,
+    Visitor,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub(crate) enum Block {
+    /// Text, wrapped to the width of the paper
+    Text {
+        text: String,
+        #[serde(default)]
+        justify: Justify,
+        /// Width and height multipliers
+        #[serde(default = "default_size")]
+        size: (u8, u8),
+        #[serde(default)]
+        bold: bool,
+        #[serde(default)]
+        underline: bool,
+        /// White on black
+        #[serde(default)]
+        invert: bool,
+    },
+    /// A line across the width of the paper
+    Separator {
+        #[serde(default)]
+        style: Separator,
+    },
+    /// Blank lines
+    Feed {
+        #[serde(default = "default_lines")]
+        lines: u8,
+    },
+    /// An image file, converted to black and white
+    Image {
+        path: PathBuf,
+        #[serde(default)]
+        justify: Justify,
+    },
+    /// A QR code
+    QrCode {
+        data: String,
+        #[serde(default)]
+        justify: Justify,
+        /// Size of each module in dots
+        #[serde(default = "default_qr_size")]
+        size: u8,
+    },
+}
+
+fn default_size() -> (u8, u8) {
+    (1, 1)
+}
+
+fn default_lines() -> u8 {
+    1
+}
+
+fn default_qr_size() -> u8 {
+    6
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Justify {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+impl From<Justify> for JustifyMode {
+    fn from(justify: Justify) -> Self {
+        match justify {
+            Justify::Left => Self::LEFT,
+            Justify::Center => Self::CENTER,
+            Justify::Right => Self::RIGHT,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Separator {
+    #[default]
+    Simple,
+    Double,
+    Dotted,
+    Dashed,
+}
+
+impl From<Separator> for LineStyle<'static> {
+    fn from(separator: Separator) -> Self {
+        match separator {
+            Separator::Simple => Self::Simple,
+            Separator::Double => Self::Double,
+            Separator::Dotted => Self::Dotted,
+            Separator::Dashed => Self::Dashed,
+        }
+    }
+}
+
+/// How a character's receipts differ from the template.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CharacterStyle {
+    /// Text size of the character's name in the header
+    pub header_size: Option<(u8, u8)>,
+    /// Print the character's name in the header white on black
+    #[serde(default)]
+    pub invert_name: bool,
+    /// Style of all separator lines
+    pub separator: Option<Separator>,
+    /// Alignment of the character's name and messages
+    pub message_justify: Option<Justify>,
+    /// Line printed at the start of the footer
+    pub sign_off: Option<String>,
+}
+
+impl CharacterStyle {
+    /// Restyles the blocks of one section of a template.
+    pub(crate) fn apply(&self, section: Section, blocks: &[Block]) -> Vec<Block> {
+        let mut blocks = blocks.to_vec();
+
+        if let (Section::Footer, Some(sign_off)) = (section, &self.sign_off) {
+            blocks.insert(
+                0,
+                Block::Text {
+                    text: sign_off.clone(),
+                    justify: self.message_justify.unwrap_or(Justify::Center),
+                    size: default_size(),
+                    bold: false,
+                    underline: false,
+                    invert: false,
+                },
+            );
+        }
+
+        for block in &mut blocks {
+            match block {
+                Block::Separator { style } => {
+                    *style = self.separator.unwrap_or(*style);
+                }
+                Block::Text {
+                    text, size, invert, ..
+                } if section == Section::Header && text.contains("{character.name}") => {
+                    *size = self.header_size.unwrap_or(*size);
+                    *invert |= self.invert_name;
+                }
+                Block::Text { text, justify, .. }
+                    if section == Section::CharacterMessage
+                        && (text.contains("{name}") || text.contains("{message}")) =>
+                {
+                    *justify = self.message_justify.unwrap_or(*justify);
+                }
+                _ => {}
+            }
+        }
+
+        blocks
+    }
+
+    /// Checks for anything that would only fail once a receipt is being printed.
+    pub(crate) fn validate(&self) {
+        if let Some((width, height)) = self.header_size {
+            assert!(
+                (1..=8).contains(&width) && (1..=8).contains(&height),
+                "Character header size must be between 1 and 8, got [{width}, {height}]"
+            );
+        }
+    }
+}
+
+/// Replaces each `{key}` in `text` with its value, placeholders without a value are left as is.
+pub(crate) fn substitute(text: &str, values: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let value = rest.find('}').and_then(|end| {
+            values
+                .iter()
+                .find(|(key, _)| *key == &rest[1..end])
+                .map(|(_, value)| (end, value))
+        });
+
+        match value {
+            Some((end, value)) => {
+                out.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_template_is_valid() {
+        let template = ReceiptTemplate::default();
+        assert!(!template.header.is_empty());
+        assert!(!template.footer.is_empty());
+    }
+
+    #[test]
+    fn substitute_placeholders() {
+        assert_eq!(
+            substitute(
+                "{name} said: {message} ({name})",
+                &[("name", "Ember"), ("message", "hello")]
+            ),
+            "Ember said: hello (Ember)"
+        );
+    }
+
+    #[test]
+    fn values_are_not_substituted() {
+        assert_eq!(
+            substitute("{message}", &[("message", "{name}"), ("name", "Ember")]),
+            "{name}"
+        );
+    }
+
+    #[test]
+    fn unknown_placeholders_are_kept() {
+        assert_eq!(substitute("{nope}", &[("name", "Ember")]), "{nope}");
+    }
+
+    #[test]
+    fn character_style() {
+        let template = ReceiptTemplate::default();
+        let style = CharacterStyle {
+            header_size: Some((3, 3)),
+            invert_name: true,
+            separator: Some(Separator::Double),
+            message_justify: Some(Justify::Left),
+            sign_off: Some("Bye!".to_string()),
+        };
+
+        let header = style.apply(Section::Header, template.section(Section::Header));
+        assert!(header.iter().any(|b| matches!(
+            b,
+            Block::Text { text, size: (3, 3), invert: true, .. } if text == "{character.name}"
+        )));
+        assert!(header.iter().all(|b| !matches!(
+            b,
+            Block::Separator {
+                style: Separator::Simple
+            }
+        )));
+
+        let messages = style.apply(
+            Section::CharacterMessage,
+            template.section(Section::CharacterMessage),
+        );
+        assert!(matches!(
+            &messages[1],
+            Block::Text { text, justify: Justify::Left, .. } if text == "{message}"
+        ));
+        // Spacing is left as it is
+        assert!(matches!(
+            messages.last(),
+            Some(Block::Text {
+                justify: Justify::Center,
+                ..
+            })
+        ));
+
+        let footer = style.apply(Section::Footer, template.section(Section::Footer));
+        assert!(matches!(&footer[0], Block::Text { text, .. } if text == "Bye!"));
+    }
+
+    #[test]
+    fn default_character_style_changes_nothing() {
+        let template = ReceiptTemplate::default();
+        let blocks = template.section(Section::Header);
+        let styled = CharacterStyle::default().apply(Section::Header, blocks);
+        assert_eq!(format!("{blocks:?}"), format!("{styled:?}"));
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let result = toml::from_str::<ReceiptTemplate>(
+            r#"
+            user_message = []
+            character_message = []
+            footer = []
+
+            [[header]]
+            type = "text"
+            text = "hi"
+            colour = "red"
+            "#,
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,152 @@
+//! Conversion of arbitrary Unicode text into something each output device can render.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Charset {
+    /// ISO 8859-1, as covered by the controller's fonts
+    Latin1,
+    /// Code page 858, as selected on the thermal printer
+    Cp858,
+}
+
+impl Charset {
+    fn contains(self, c: char) -> bool {
+        let latin1 = matches!(c, '\n' | ' '..='~' | '\u{a0}'..='\u{ff}');
+
+        match self {
+            Self::Latin1 => latin1,
+            // escpos' CP858 table has no soft hyphen and maps both Ò and Ô to the glyph for Ò
+            Self::Cp858 => (latin1 && !matches!(c, '\u{ad}' | 'Ò' | 'Ô')) || c == '€',
+        }
+    }
+}
+
+/// True for emoji and other pictographs, which have no sensible textual equivalent.
+fn is_pictograph(c: char) -> bool {
+    matches!(
+        c,
+        '\u{2600}'..='\u{27bf}' | '\u{2b00}'..='\u{2bff}' | '\u{1f000}'..='\u{1faff}'
+    )
+}
+
+/// Converts text to only use characters from the given character set.
+///
+/// Supported characters are left alone, anything else is transliterated to the closest ASCII
+/// equivalent. Emoji are dropped.
+pub(crate) fn transliterate(s: &str, charset: Charset) -> String {
+    let mut out = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        if charset.contains(c) {
+            out.push(c);
+        } else if c == '\t' {
+            out.push(' ');
+        } else if is_pictograph(c) || c.is_control() {
+            continue;
+        } else {
+            out.push_str(deunicode::deunicode_char(c).unwrap_or("?"));
+        }
+    }
+
+    out
+}
+
+/// Shortens text to at most `max_len` bytes, ending it with "..." if anything was cut.
+pub(crate) fn truncate(s: &str, max_len: usize) -> String {
+    if s.len() <= max_len {
+        return s.to_string();
+    }
+
+    let mut end = max_len.saturating_sub(3);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}...", &s[..end])
+}
+
+/// Converts text for the controller, shortening it to fit in `N` bytes rather than failing.
+pub(crate) fn screen_text<const N: usize>(s: &str) -> heapless::String<N> {
+    truncate(&transliterate(s, Charset::Latin1), N)
+        .as_str()
+        .try_into()
+        .expect("Truncated text should fit")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_is_unchanged() {
+        let s = "Hello, world! (1 + 2 = 3) ~ \"quoted\"\nnext line";
+        assert_eq!(transliterate(s, Charset::Latin1), s);
+        assert_eq!(transliterate(s, Charset::Cp858), s);
+    }
+
+    #[test]
+    fn latin1_is_kept() {
+        let s = "Café crème, ¿qué tal? Grüße aus Åland ½ £5";
+        assert_eq!(transliterate(s, Charset::Latin1), s);
+        assert_eq!(transliterate(s, Charset::Cp858), s);
+    }
+
+    #[test]
+    fn typographic_punctuation() {
+        assert_eq!(
+            transliterate("It’s “fine” – really… • ok — yes", Charset::Latin1),
+            "It's \"fine\" - really... * ok -- yes"
+        );
+    }
+
+    #[test]
+    fn latin_extended() {
+        assert_eq!(transliterate("Łódź", Charset::Latin1), "Lódz");
+        assert_eq!(transliterate("Erdoğan", Charset::Cp858), "Erdogan");
+        assert_eq!(transliterate("Œuvre", Charset::Latin1), "OEuvre");
+    }
+
+    #[test]
+    fn non_latin_scripts() {
+        assert_eq!(transliterate("Жар", Charset::Latin1), "Zhar");
+        assert_eq!(transliterate("Αθήνα", Charset::Cp858), "Athena");
+    }
+
+    #[test]
+    fn euro_sign() {
+        assert_eq!(transliterate("5€", Charset::Latin1), "5EUR");
+        assert_eq!(transliterate("5€", Charset::Cp858), "5€");
+    }
+
+    #[test]
+    fn printer_table_gaps() {
+        assert_eq!(transliterate("ÒÔÓ", Charset::Latin1), "ÒÔÓ");
+        assert_eq!(transliterate("ÒÔÓ", Charset::Cp858), "OOÓ");
+        assert_eq!(transliterate("co\u{ad}op", Charset::Cp858), "coop");
+    }
+
+    #[test]
+    fn emoji_are_dropped() {
+        assert_eq!(transliterate("Hi 😀!", Charset::Latin1), "Hi !");
+        assert_eq!(transliterate("❤️ you", Charset::Cp858), " you");
+        assert_eq!(transliterate("👩‍🔬", Charset::Latin1), "");
+    }
+
+    #[test]
+    fn truncate_to_length() {
+        assert_eq!(truncate("short", 8), "short");
+        assert_eq!(truncate("a bit longer", 8), "a bit...");
+        assert_eq!(truncate("caféé", 6), "caf...");
+    }
+
+    #[test]
+    fn screen_text_fits() {
+        let s: heapless::String<8> = screen_text("Grüße 👋");
+        assert_eq!(s, "Grüße ");
+        let s: heapless::String<8> = screen_text("a bit longer");
+        assert_eq!(s, "a bit...");
+    }
+
+    #[test]
+    fn control_characters() {
+        assert_eq!(transliterate("a\tb\rc\u{7}", Charset::Latin1), "a bc");
+    }
+}
@@ -0,0 +1,296 @@
+//! Checks that replies from the characters fit on the controller and printer, as the models do not
+//! always keep to the length asked of them in their system prompts.
+
+use crate::{
+    conversation::VnOutput,
+    text::{transliterate, Charset},
+};
+use serde::{Deserialize, Serialize};
+
+/// Fewest choices a reply can give, e.g. yes or no
+pub(crate) const MIN_CHOICES: usize = 2;
+
+/// Bytes the controller has for the response and each choice, once transliterated
+const RESPONSE_CAPACITY: usize = 512;
+const CHOICE_CAPACITY: usize = 256;
+
+/// What a character's replies must keep to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ReplyLimits {
+    /// Characters in the response
+    pub max_response_chars: usize,
+    /// Characters in each choice
+    pub max_choice_chars: usize,
+    /// Lines each choice is wrapped onto on the controller, in its largest font
+    pub max_choice_lines: u32,
+    /// Times the model is asked to correct a reply before it is used as it is
+    pub max_corrections: u8,
+}
+
+impl Default for ReplyLimits {
+    fn default() -> Self {
+        Self {
+            max_response_chars: 300,
+            max_choice_chars: 100,
+            max_choice_lines: 3,
+            max_corrections: 2,
+        }
+    }
+}
+
+impl ReplyLimits {
+    /// Lists everything wrong with a reply, which is empty if nothing is.
+    ///
+    /// `max_choices` is how many choices the controller can show.
+    pub(crate) fn validate(&self, output: &VnOutput, max_choices: usize) -> Vec<String> {
+        let mut violations = Vec::new();
+
+        let response = &output.response;
+        if response.trim().is_empty() {
+            violations.push("The response is empty.".to_string());
+        } else if response.chars().count() > self.max_response_chars
+            || transliterate(response, Charset::Latin1).len() > RESPONSE_CAPACITY
+        {
+            violations.push(format!(
+                "The response is longer than {} characters.",
+                self.max_response_chars
+            ));
+        }
+
+        if let Some(farewell) = &output.farewell {
+            if farewell.chars().count() > self.max_response_chars {
+                violations.push(format!(
+                    "The farewell is longer than {} characters.",
+                    self.max_response_chars
+                ));
+            }
+        }
+
+        // The choices are not shown once the conversation ends
+        if output.end_conversation {
+            return violations;
+        }
+
+        let choices = &output.user_replies;
+        if !(MIN_CHOICES..=max_choices).contains(&choices.len()) {
+            violations.push(format!(
+                "Give between {MIN_CHOICES} and {max_choices} user_replies, not {}.",
+                choices.len()
+            ));
+        }
+
+        for (i, choice) in choices.iter().enumerate() {
+            let n = i + 1;
+            if choice.trim().is_empty() {
+                violations.push(format!(
+                    "User reply {n} is empty, set end_conversation to end the conversation instead."
+                ));
+            } else if choice.chars().count() > self.max_choice_chars
+                || transliterate(choice, Charset::Latin1).len() > CHOICE_CAPACITY
+            {
+                violations.push(format!(
+                    "User reply {n} is longer than {} characters.",
+                    self.max_choice_chars
+                ));
+            } else if screens::choice_lines(choice, screens::screen_box()) > self.max_choice_lines {
+                violations.push(format!(
+                    "User reply {n} is too long to show, keep it to one short sentence."
+                ));
+            }
+
+            if choices[..i]
+                .iter()
+                .any(|other| !choice.trim().is_empty() && normalise(other) == normalise(choice))
+            {
+                violations.push(format!(
+                    "User reply {n} is the same as another of the things the user could say."
+                ));
+            }
+        }
+
+        violations
+    }
+}
+
+/// Fixes the choices of a reply that is still invalid once the corrections are used up, so that
+/// the visitor can still pick one.
+///
+/// Empty and repeated choices are dropped, extra ones cut off, and too few are made up from
+/// `spares` (e.g. the character's opening lines). If that is still not enough the conversation
+/// ends instead.
+pub(crate) fn repair(output: &mut VnOutput, max_choices: usize, spares: &[String]) {
+    if output.end_conversation {
+        return;
+    }
+
+    let mut choices: Vec<String> = Vec::new();
+    let add = |choices: &mut Vec<String>, choice: &String| {
+        if !choice.trim().is_empty() && !choices.iter().any(|c| normalise(c) == normalise(choice)) {
+            choices.push(choice.clone());
+        }
+    };
+    for choice in &output.user_replies {
+        if choices.len() == max_choices {
+            break;
+        }
+        add(&mut choices, choice);
+    }
+    for spare in spares {
+        if choices.len() >= MIN_CHOICES {
+            break;
+        }
+        add(&mut choices, spare);
+    }
+
+    if choices.len() < MIN_CHOICES {
+        output.end_conversation = true;
+    }
+    output.user_replies = choices;
+}
+
+fn normalise(s: &str) -> String {
+    s.trim().to_lowercase()
+}
+
+/// Message asking the model to fix its last reply.
+pub(crate) fn correction_prompt(violations: &[String]) -> String {
+    format!(
+        "Your last reply did not follow the rules:\n- {}\nReply again, fixing these problems.",
+        violations.join("\n- ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(response: &str, choices: &[&str]) -> VnOutput {
+        VnOutput {
+            response: response.to_string(),
+            user_replies: choices.iter().map(|c| c.to_string()).collect(),
+            end_conversation: false,
+            farewell: None,
+        }
+    }
+
+    fn ending(response: &str, farewell: Option<&str>) -> VnOutput {
+        VnOutput {
+            end_conversation: true,
+            farewell: farewell.map(str::to_string),
+            ..output(response, &[])
+        }
+    }
+
+    #[test]
+    fn valid() {
+        let limits = ReplyLimits::default();
+        assert!(limits
+            .validate(&output("Hello!", &["Hi", "Who are you?", "Bye"]), 3)
+            .is_empty());
+        assert!(limits
+            .validate(&output("Well?", &["Yes", "No"]), 3)
+            .is_empty());
+        assert!(limits.validate(&ending("Bye!", None), 3).is_empty());
+        assert!(limits
+            .validate(&ending("Oh, is that the time?", Some("Goodbye!")), 3)
+            .is_empty());
+    }
+
+    #[test]
+    fn empty() {
+        let limits = ReplyLimits::default();
+        assert_eq!(limits.validate(&output("", &["a", "b", "c"]), 3).len(), 1);
+        // Without ending the conversation
+        assert_eq!(limits.validate(&output("Bye!", &["", "", ""]), 3).len(), 3);
+        assert_eq!(
+            limits.validate(&output("Hi", &["a", " ", "c"]), 3),
+            ["User reply 2 is empty, set end_conversation to end the conversation instead."]
+        );
+    }
+
+    #[test]
+    fn choice_count() {
+        let limits = ReplyLimits::default();
+        assert_eq!(
+            limits.validate(&output("Hi", &["a"]), 3),
+            ["Give between 2 and 3 user_replies, not 1."]
+        );
+        let four = output("Hi", &["a", "b", "c", "d"]);
+        assert_eq!(limits.validate(&four, 3).len(), 1);
+        assert!(limits.validate(&four, 4).is_empty());
+    }
+
+    #[test]
+    fn too_long() {
+        let limits = ReplyLimits {
+            max_response_chars: 10,
+            max_choice_chars: 20,
+            ..Default::default()
+        };
+        let violations = limits.validate(
+            &output(
+                "Far too long a response",
+                &["a", "b", "a choice that is too long"],
+            ),
+            3,
+        );
+        assert_eq!(violations.len(), 2);
+        assert!(violations[1].starts_with("User reply 3"));
+
+        let violations = limits.validate(&ending("Bye!", Some("Far too long a farewell")), 3);
+        assert_eq!(violations, ["The farewell is longer than 10 characters."]);
+    }
+
+    #[test]
+    fn too_many_lines() {
+        let limits = ReplyLimits {
+            max_choice_lines: 1,
+            ..Default::default()
+        };
+        let violations = limits.validate(
+            &output("Hi", &["a", "b", "This choice wraps onto a second line"]),
+            3,
+        );
+        assert_eq!(violations.len(), 1);
+    }
+
+    #[test]
+    fn duplicates() {
+        let violations =
+            ReplyLimits::default().validate(&output("Hi", &["Hello", "Bye", " hello"]), 3);
+        assert_eq!(
+            violations,
+            ["User reply 3 is the same as another of the things the user could say."]
+        );
+    }
+
+    #[test]
+    fn repaired() {
+        let spares = ["Hello!".to_string(), "Who are you?".to_string()];
+
+        let mut reply = output("Hi", &["Bye", "", " bye"]);
+        repair(&mut reply, 3, &spares);
+        assert_eq!(reply.user_replies, ["Bye", "Hello!"]);
+        assert!(!reply.end_conversation);
+        assert!(ReplyLimits::default().validate(&reply, 3).is_empty());
+
+        let mut reply = output("Hi", &["a", "b", "c", "d"]);
+        repair(&mut reply, 3, &spares);
+        assert_eq!(reply.user_replies, ["a", "b", "c"]);
+
+        // Ends the conversation if there is nothing left to offer
+        let mut reply = output("Hi", &["", " "]);
+        repair(&mut reply, 3, &[]);
+        assert!(reply.end_conversation);
+        assert!(ReplyLimits::default().validate(&reply, 3).is_empty());
+    }
+
+    #[test]
+    fn prompt() {
+        assert_eq!(
+            correction_prompt(&["a".to_string(), "b".to_string()]),
+            "Your last reply did not follow the rules:\n- a\n- b\nReply again, fixing these problems."
+        );
+    }
+}
@@ -0,0 +1,120 @@
+//! Loads the characters' models before visitors arrive, so that the first reply from each
+//! character is not held up by Ollama loading its weights.
+
+use crate::character::{Character, CharacterCollection};
+use log::{debug, info, warn};
+use ollama_rs::{
+    generation::{
+        chat::{request::ChatMessageRequest, ChatMessage},
+        parameters::{KeepAlive, TimeUnit},
+    },
+    Ollama,
+};
+use std::time::{Duration, Instant};
+
+/// How long a model took to load, or `None` if it failed to.
+#[derive(Debug, Clone)]
+pub(crate) struct ModelLoad {
+    pub model: String,
+    pub duration: Option<Duration>,
+}
+
+/// How long to keep models loaded after a request, in minutes, zero keeps them loaded.
+pub(crate) fn keep_alive(minutes: u64) -> KeepAlive {
+    match minutes {
+        0 => KeepAlive::Indefinitely,
+        time => KeepAlive::Until {
+            time,
+            unit: TimeUnit::Minutes,
+        },
+    }
+}
+
+/// Loads each character's model with a short generation, which is quick if it is already loaded.
+///
+/// If `idle` is set, only models that no character has used for that long are loaded, so that
+/// models in use by a conversation are left alone.
+pub(crate) async fn warm_up(
+    ollama: &Ollama,
+    characters: &CharacterCollection,
+    idle: Option<Duration>,
+) -> Vec<ModelLoad> {
+    let mut loads: Vec<ModelLoad> = Vec::new();
+    let mut skipped: Vec<&str> = Vec::new();
+
+    for character in &characters.characters {
+        let model = character.model();
+        // Characters sharing a base model load it once
+        if loads.iter().any(|l| l.model == model) || skipped.contains(&model) {
+            continue;
+        }
+
+        if let Some(idle) = idle {
+            let in_use = characters
+                .characters
+                .iter()
+                .any(|c| c.model() == model && c.used_within(idle));
+            if in_use {
+                debug!("Model {model} was used recently, not warming it up");
+                skipped.push(model);
+                continue;
+            }
+        }
+
+        let start = Instant::now();
+        let duration = match ollama.send_chat_messages(request(character)).await {
+            Ok(_) => {
+                let duration = start.elapsed();
+                info!(
+                    "Model {} ready after {:.1} s",
+                    character.model(),
+                    duration.as_secs_f32()
+                );
+                Some(duration)
+            }
+            Err(e) => {
+                warn!("Failed to load model {}: {e}", character.model());
+                None
+            }
+        };
+
+        loads.push(ModelLoad {
+            model: character.model().to_string(),
+            duration,
+        });
+    }
+
+    loads
+}
+
+/// Generates a single token, with the options the character would use so that the model is not
+/// loaded again for its first real request.
+fn request(character: &Character) -> ChatMessageRequest {
+    let mut messages = character.initial_history();
+    messages.push(ChatMessage::user("Hello".to_string()));
+
+    // Not recorded as a use of the model, so that idle models keep being warmed up
+    let mut request = character.chat_request_for(character.model(), messages);
+    request.options = request.options.map(|o| o.num_predict(1));
+    request
+}
+
+/// Unloads the models of disabled characters, unless an enabled character uses them too.
+pub(crate) async fn unload_disabled(ollama: &Ollama, characters: &CharacterCollection) {
+    for character in &characters.disabled {
+        let model = character.model();
+        if characters.characters.iter().any(|c| c.model() == model) {
+            continue;
+        }
+
+        info!(
+            "Unloading model {model} of disabled character {}",
+            character.name
+        );
+        let request = ChatMessageRequest::new(model.to_string(), Vec::new())
+            .keep_alive(KeepAlive::UnloadOnCompletion);
+        if let Err(e) = ollama.send_chat_messages(request).await {
+            warn!("Failed to unload model {model}: {e}");
+        }
+    }
+}
@@ -5,17 +5,34 @@ use embedded_graphics::{
     prelude::RawData,
 };
 use postcard_rpc::{endpoints, topics, TopicDirection};
-use postcard_schema::Schema;
+use postcard_schema::{key::Key, Schema};
 use serde::{Deserialize, Serialize};
 
 endpoints! {
     list = ENDPOINT_LIST;
     omit_std = true;
-    | EndpointTy   | RequestTy | ResponseTy | Path          |
-    | ----------   | --------- | ---------- | ----          |
-    | SetDisplay   | Screen    | ()         | "set_display" |
+    | EndpointTy     | RequestTy           | ResponseTy   | Path              |
+    | ----------     | ---------           | ----------   | ----              |
+    | GetDeviceInfo  | ()                  | DeviceInfo   | "device_info"     |
+    | SetDisplay     | Screen              | ScreenReport | "set_display"     |
+    | SetBrightness  | u8                  | ()           | "set_brightness"  |
+    | SetScreensaver | ScreensaverSettings | ()           | "set_screensaver" |
 }
 
+/// Hash of the schema of every type exchanged between the host and controller.
+///
+/// Host and firmware built from different revisions of this crate will disagree on this value.
+pub const SCHEMA_HASH: u64 = u64::from_le_bytes(
+    Key::for_path::<(
+        DeviceInfo,
+        Screen,
+        ScreenReport,
+        ScreensaverSettings,
+        ButtonAction,
+    )>("icd")
+    .to_bytes(),
+);
+
 topics! {
     list = TOPICS_IN_LIST;
     direction = TopicDirection::ToServer;
@@ -31,11 +48,56 @@ topics! {
     | ButtonActionPerformed | ButtonAction | "button_action" |     |
 }
 
+#[derive(Debug, defmt::Format, Clone, Serialize, Deserialize, Schema)]
+pub struct DeviceInfo {
+    pub firmware_version: heapless::String<16>,
+    pub git_hash: heapless::String<40>,
+    /// Unique to each controller, the same as its USB serial number
+    pub serial_number: heapless::String<16>,
+    pub schema_hash: u64,
+    pub display_width: u16,
+    pub display_height: u16,
+    pub button_count: u8,
+    pub screens: heapless::Vec<ScreenKind, 8>,
+}
+
+impl DeviceInfo {
+    pub fn supports(&self, kind: ScreenKind) -> bool {
+        self.screens.contains(&kind)
+    }
+
+    /// Most choices that can be picked from, with one button kept for ending the conversation.
+    pub fn max_choices(&self) -> usize {
+        usize::from(self.button_count.saturating_sub(1)).min(MAX_CHOICES)
+    }
+}
+
 #[derive(Debug, defmt::Format, Clone, Serialize, Deserialize, Schema)]
 #[allow(clippy::large_enum_variant)]
 pub enum Screen {
     CharacterSelect(CharacterSelectScreen),
     Choices(ChoiceScreen),
+    Reply(ReplyScreen),
+    Attention(AttentionScreen),
+}
+
+impl Screen {
+    pub fn kind(&self) -> ScreenKind {
+        match self {
+            Self::CharacterSelect(_) => ScreenKind::CharacterSelect,
+            Self::Choices(_) => ScreenKind::Choices,
+            Self::Reply(_) => ScreenKind::Reply,
+            Self::Attention(_) => ScreenKind::Attention,
+        }
+    }
+}
+
+#[derive(Debug, defmt::Format, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub enum ScreenKind {
+    CharacterSelect,
+    Choices,
+    Reply,
+    Attention,
 }
 
 #[derive(Debug, defmt::Format, Clone, Serialize, Deserialize, Schema)]
@@ -86,15 +148,16 @@ pub struct CharacterSelectScreen {
 
 pub type ChoiceString = heapless::String<256>;
 
+/// Most choices a choice screen can show.
+pub const MAX_CHOICES: usize = 4;
+
 #[derive(Debug, defmt::Format, Clone, Serialize, Deserialize, Schema)]
 pub struct ChoiceScreen {
     text_colour: u32,
     background_colour: u32,
     margin_colour: u32,
 
-    choice_1: ChoiceString,
-    choice_2: ChoiceString,
-    choice_3: ChoiceString,
+    choices: heapless::Vec<ChoiceString, MAX_CHOICES>,
 }
 
 impl ChoiceScreen {
@@ -102,17 +165,13 @@ impl ChoiceScreen {
         text_colour: Rgb666,
         background_colour: Rgb666,
         margin_colour: Rgb666,
-        choice_1: ChoiceString,
-        choice_2: ChoiceString,
-        choice_3: ChoiceString,
+        choices: heapless::Vec<ChoiceString, MAX_CHOICES>,
     ) -> Self {
         Self {
             text_colour: rgb666_to_u32(text_colour),
             background_colour: rgb666_to_u32(background_colour),
             margin_colour: rgb666_to_u32(margin_colour),
-            choice_1,
-            choice_2,
-            choice_3,
+            choices,
         }
     }
 
@@ -128,24 +187,97 @@ impl ChoiceScreen {
         rgb666_from_u32(self.margin_colour)
     }
 
-    pub fn choice_text(&self, idx: usize) -> &str {
-        match idx {
-            0 => &self.choice_1,
-            1 => &self.choice_2,
-            2 => &self.choice_3,
-            _ => unreachable!(),
-        }
+    pub fn choices(&self) -> &[ChoiceString] {
+        &self.choices
     }
 }
 
+#[derive(Debug, defmt::Format, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub enum TextFit {
+    /// Text fits in the default font
+    Fits,
+    /// Text only fits in a smaller font
+    Shrunk,
+    /// Text does not fit even in the smallest font, so is scrolled
+    Scrolled,
+    /// Text is split across several pages, which are cycled through
+    Paged,
+}
+
+#[derive(Debug, defmt::Format, Clone, Default, Serialize, Deserialize, Schema)]
+pub struct ScreenReport {
+    /// How each block of text on the screen was fitted, in the order they are laid out
+    pub text: heapless::Vec<TextFit, { MAX_CHOICES + 1 }>,
+}
+
+impl ScreenReport {
+    pub fn overflowed(&self) -> bool {
+        self.text.contains(&TextFit::Scrolled)
+    }
+}
+
+#[derive(Debug, defmt::Format, Clone, Serialize, Deserialize, Schema)]
+pub struct ScreensaverSettings {
+    /// Seconds without a button press before the screensaver starts, zero disables it
+    pub timeout_secs: u32,
+    /// Seconds each screensaver card is shown for
+    pub cycle_secs: u32,
+    /// Backlight brightness while the screensaver is shown
+    pub brightness: u8,
+}
+
+pub type ResponseString = heapless::String<512>;
+
+/// The character's latest reply, shown above the choices for the user's next message.
+#[derive(Debug, defmt::Format, Clone, Serialize, Deserialize, Schema)]
+pub struct ReplyScreen {
+    pub response: ResponseString,
+    pub choices: ChoiceScreen,
+}
+
+/// A problem that needs the kiosk's operator to step in, e.g. the printer running out of paper.
+#[derive(Debug, defmt::Format, Clone, Serialize, Deserialize, Schema)]
+pub struct AttentionScreen {
+    pub title: heapless::String<32>,
+    pub message: heapless::String<128>,
+}
+
 #[derive(Debug, defmt::Format, Clone, Serialize, Deserialize, Schema)]
 pub enum ButtonAction {
     Fn1,
     Fn2,
     Fn3,
+    /// Only on controllers with four choice buttons
+    Fn4,
     EndConversation,
 }
 
+impl ButtonAction {
+    /// The choice picked by pressing this button, when a screen shows `count` choices on a
+    /// controller with `buttons` choice buttons.
+    ///
+    /// Choice buttons run down the side of the screen, so two choices are picked with the buttons
+    /// at either end.
+    pub fn choice(&self, count: usize, buttons: usize) -> Option<usize> {
+        let button = match self {
+            Self::Fn1 => 0,
+            Self::Fn2 => 1,
+            Self::Fn3 => 2,
+            Self::Fn4 => 3,
+            Self::EndConversation => return None,
+        };
+
+        match count {
+            2 if buttons > 2 => match button {
+                0 => Some(0),
+                b if b == buttons - 1 => Some(1),
+                _ => None,
+            },
+            _ => (button < count.min(buttons)).then_some(button),
+        }
+    }
+}
+
 fn rgb666_to_u32(c: Rgb666) -> u32 {
     let c: RawU24 = c.into();
     c.into_inner()
@@ -0,0 +1,37 @@
+use super::CharacterCardScreen;
+use embedded_graphics::{
+    pixelcolor::Rgb666,
+    prelude::{DrawTarget, RgbColor, WebColors},
+    Drawable,
+};
+use icd::CharacterDetails;
+
+pub struct AttentionScreen {
+    content: icd::AttentionScreen,
+}
+
+impl AttentionScreen {
+    pub fn new(content: icd::AttentionScreen) -> Self {
+        Self { content }
+    }
+}
+
+impl Drawable for AttentionScreen {
+    type Color = Rgb666;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        // Laid out the same as a character card, in colours that stand out from every character
+        CharacterCardScreen::new(CharacterDetails::new(
+            Rgb666::BLACK,
+            Rgb666::CSS_GOLD,
+            Rgb666::CSS_RED,
+            self.content.title.clone(),
+            self.content.message.as_str().try_into().unwrap(),
+        ))
+        .draw(target)
+    }
+}
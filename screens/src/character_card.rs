@@ -0,0 +1,74 @@
+use embedded_graphics::{
+    geometry::AnchorPoint,
+    mono_font::{
+        iso_8859_1::{FONT_10X20, FONT_9X18},
+        MonoTextStyle,
+    },
+    pixelcolor::Rgb666,
+    prelude::{DrawTarget, Primitive, Size},
+    primitives::PrimitiveStyleBuilder,
+    Drawable,
+};
+use embedded_text::{
+    alignment::{HorizontalAlignment, VerticalAlignment},
+    style::TextBoxStyleBuilder,
+    TextBox,
+};
+
+pub struct CharacterCardScreen {
+    content: icd::CharacterDetails,
+}
+
+impl CharacterCardScreen {
+    pub fn new(content: icd::CharacterDetails) -> Self {
+        Self { content }
+    }
+}
+
+impl Drawable for CharacterCardScreen {
+    type Color = Rgb666;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let rect = target.bounding_box();
+
+        rect.into_styled(
+            PrimitiveStyleBuilder::new()
+                .stroke_width(4)
+                .stroke_color(self.content.margin_colour())
+                .fill_color(self.content.background_colour())
+                .build(),
+        )
+        .draw(target)?;
+
+        TextBox::with_textbox_style(
+            &self.content.name,
+            rect.resized(Size::new(rect.size.width, 64), AnchorPoint::TopCenter),
+            MonoTextStyle::new(&FONT_10X20, self.content.text_colour()),
+            TextBoxStyleBuilder::new()
+                .alignment(HorizontalAlignment::Center)
+                .vertical_alignment(VerticalAlignment::Middle)
+                .build(),
+        )
+        .draw(target)?;
+
+        TextBox::with_textbox_style(
+            &self.content.description,
+            rect.resized(
+                Size::new(rect.size.width - 32, rect.size.height - 80),
+                AnchorPoint::BottomCenter,
+            ),
+            MonoTextStyle::new(&FONT_9X18, self.content.text_colour()),
+            TextBoxStyleBuilder::new()
+                .alignment(HorizontalAlignment::Center)
+                .vertical_alignment(VerticalAlignment::Top)
+                .build(),
+        )
+        .draw(target)?;
+
+        Ok(())
+    }
+}
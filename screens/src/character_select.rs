@@ -2,7 +2,7 @@ use embedded_graphics::{
     geometry::AnchorPoint,
     image::Image,
     mono_font::{
-        ascii::{FONT_10X20, FONT_9X18},
+        iso_8859_1::{FONT_10X20, FONT_9X18},
         MonoTextStyle, MonoTextStyleBuilder,
     },
     pixelcolor::Rgb666,
@@ -18,12 +18,12 @@ use embedded_text::{
 };
 use tinybmp::Bmp;
 
-pub(crate) struct CharacterSelectScreen {
+pub struct CharacterSelectScreen {
     content: icd::CharacterSelectScreen,
 }
 
 impl CharacterSelectScreen {
-    pub(crate) fn new(content: icd::CharacterSelectScreen) -> Self {
+    pub fn new(content: icd::CharacterSelectScreen) -> Self {
         Self { content }
     }
 }
@@ -57,7 +57,7 @@ impl Drawable for CharacterSelectScreen {
         )
         .draw(target)?;
 
-        let boxes = super::choice_boxes(target);
+        let boxes = super::choice_boxes(target.bounding_box(), 3);
 
         let name_textbox_style = TextBoxStyleBuilder::new()
             .alignment(HorizontalAlignment::Center)
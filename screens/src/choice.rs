@@ -0,0 +1,125 @@
+use super::fitted_text::{text_rect, textbox_style, FittedText};
+use embedded_graphics::{
+    mono_font::MonoTextStyle,
+    pixelcolor::Rgb666,
+    prelude::{DrawTarget, Primitive, WebColors},
+    primitives::{PrimitiveStyleBuilder, Rectangle},
+    Drawable,
+};
+use embedded_text::TextBox;
+use heapless::Vec;
+use icd::{TextFit, MAX_CHOICES};
+
+/// Reports how the text of each choice fits when laid out in the given area.
+pub(super) fn choices_fit(
+    content: &icd::ChoiceScreen,
+    area: Rectangle,
+) -> Vec<TextFit, MAX_CHOICES> {
+    let choices = content.choices();
+    super::choice_boxes(area, choices.len())
+        .into_iter()
+        .zip(choices)
+        .map(|(rect, text)| FittedText::new(text, rect).fit)
+        .collect()
+}
+
+/// Draws each choice in its own box, laid out in the given area.
+///
+/// Once scrolling has started (`scroll_step` is non-zero) only the boxes with text that scrolls
+/// are redrawn.
+pub(super) fn draw_choices<D>(
+    target: &mut D,
+    content: &icd::ChoiceScreen,
+    area: Rectangle,
+    scroll_step: u32,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Rgb666>,
+{
+    let choices = content.choices();
+    for (rect, text) in super::choice_boxes(area, choices.len())
+        .into_iter()
+        .zip(choices)
+    {
+        let fitted = FittedText::new(text, rect);
+
+        if scroll_step != 0 && fitted.fit != TextFit::Scrolled {
+            continue;
+        }
+
+        rect.into_styled(
+            PrimitiveStyleBuilder::new()
+                .stroke_width(1)
+                .stroke_color(content.margin_colour())
+                .fill_color(content.background_colour())
+                .build(),
+        )
+        .draw(target)?;
+
+        TextBox::with_textbox_style(
+            text,
+            text_rect(rect),
+            MonoTextStyle::new(fitted.font, content.text_colour()),
+            textbox_style(),
+        )
+        .set_vertical_offset(fitted.vertical_offset(scroll_step))
+        .draw(target)?;
+    }
+
+    Ok(())
+}
+
+pub struct ChoiceScreen {
+    content: icd::ChoiceScreen,
+    /// Number of times overflowing text has been scrolled, zero when first drawn
+    scroll_step: u32,
+}
+
+impl ChoiceScreen {
+    pub fn new(content: icd::ChoiceScreen) -> Self {
+        Self {
+            content,
+            scroll_step: 0,
+        }
+    }
+
+    /// Reports how the text of each choice fits on a screen of the given size.
+    pub fn text_fit(
+        content: &icd::ChoiceScreen,
+        screen_box: Rectangle,
+    ) -> Vec<TextFit, MAX_CHOICES> {
+        choices_fit(content, screen_box)
+    }
+
+    /// True if any choice text needs scrolling to be read in full.
+    pub fn animates(&self, screen_box: Rectangle) -> bool {
+        Self::text_fit(&self.content, screen_box).contains(&TextFit::Scrolled)
+    }
+
+    /// Advances the scroll position of overflowing text, after which only the text that scrolls
+    /// is redrawn.
+    pub fn step(&mut self) {
+        self.scroll_step = self.scroll_step.wrapping_add(1).max(1);
+    }
+}
+
+impl Drawable for ChoiceScreen {
+    type Color = Rgb666;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        if self.scroll_step == 0 {
+            target.clear(Rgb666::CSS_BLACK)?;
+        }
+
+        draw_choices(
+            target,
+            &self.content,
+            target.bounding_box(),
+            self.scroll_step,
+        )
+    }
+}
@@ -0,0 +1,96 @@
+use embedded_graphics::{
+    geometry::AnchorPoint,
+    mono_font::{
+        iso_8859_1::{FONT_6X10, FONT_7X13, FONT_9X18},
+        MonoFont, MonoTextStyle,
+    },
+    pixelcolor::Rgb666,
+    prelude::{Size, WebColors},
+    primitives::Rectangle,
+};
+use embedded_text::{
+    alignment::{HorizontalAlignment, VerticalAlignment},
+    style::{TextBoxStyle, TextBoxStyleBuilder},
+};
+use icd::TextFit;
+
+/// Fonts to try when fitting text, largest first.
+const FONTS: [&MonoFont<'static>; 3] = [&FONT_9X18, &FONT_7X13, &FONT_6X10];
+
+/// Number of scroll steps to hold still for at the start and end of overflowing text.
+const SCROLL_PAUSE_STEPS: u32 = 2;
+
+pub(super) fn textbox_style() -> TextBoxStyle {
+    TextBoxStyleBuilder::new()
+        .alignment(HorizontalAlignment::Left)
+        .vertical_alignment(VerticalAlignment::Top)
+        .build()
+}
+
+/// Area of a box that text is drawn in, leaving a margin inside the border.
+pub(super) fn text_rect(rect: Rectangle) -> Rectangle {
+    rect.resized(rect.size - Size::new(8, 8), AnchorPoint::Center)
+}
+
+pub(super) fn measure_text_height(font: &MonoFont<'_>, text: &str, width: u32) -> u32 {
+    textbox_style().measure_text_height(&MonoTextStyle::new(font, Rgb666::CSS_WHITE), text, width)
+}
+
+/// Number of lines text is wrapped onto within a box, in the largest font.
+pub(super) fn text_lines(text: &str, rect: Rectangle) -> u32 {
+    let font = FONTS[0];
+    measure_text_height(font, text, text_rect(rect).size.width) / font.character_size.height
+}
+
+/// Layout of text within a box, using the largest font that fits.
+pub(super) struct FittedText {
+    pub(super) font: &'static MonoFont<'static>,
+    pub(super) fit: TextFit,
+    /// Number of lines that do not fit in the box
+    overflow_lines: u32,
+}
+
+impl FittedText {
+    pub(super) fn new(text: &str, rect: Rectangle) -> Self {
+        let rect = text_rect(rect);
+
+        for (i, font) in FONTS.into_iter().enumerate() {
+            if measure_text_height(font, text, rect.size.width) <= rect.size.height {
+                return Self {
+                    font,
+                    fit: if i == 0 {
+                        TextFit::Fits
+                    } else {
+                        TextFit::Shrunk
+                    },
+                    overflow_lines: 0,
+                };
+            }
+        }
+
+        // Nothing fits, so scroll the text in the smallest font
+        let font = FONTS[FONTS.len() - 1];
+        let height = measure_text_height(font, text, rect.size.width);
+        Self {
+            font,
+            fit: TextFit::Scrolled,
+            overflow_lines: height
+                .saturating_sub(rect.size.height)
+                .div_ceil(font.character_size.height),
+        }
+    }
+
+    /// Vertical offset of the text at a given scroll step.
+    pub(super) fn vertical_offset(&self, scroll_step: u32) -> i32 {
+        if self.overflow_lines == 0 {
+            return 0;
+        }
+
+        let cycle = self.overflow_lines + 2 * SCROLL_PAUSE_STEPS;
+        let line = (scroll_step % cycle)
+            .saturating_sub(SCROLL_PAUSE_STEPS)
+            .min(self.overflow_lines);
+
+        -((line * self.font.character_size.height) as i32)
+    }
+}
@@ -0,0 +1,141 @@
+//! Drawables for each screen shown on the controller's display.
+//!
+//! Shared between the controller firmware and host-side tooling that previews screens.
+
+#![cfg_attr(not(test), no_std)]
+
+mod attention;
+mod character_card;
+mod character_select;
+mod choice;
+mod fitted_text;
+mod reply;
+#[cfg(test)]
+mod snapshot_tests;
+mod splash;
+
+use embedded_graphics::{
+    geometry::AnchorPoint,
+    pixelcolor::Rgb666,
+    prelude::{DrawTarget, Point, Size},
+    primitives::Rectangle,
+    Drawable,
+};
+use heapless::Vec;
+use icd::{Screen, ScreenReport, MAX_CHOICES};
+
+pub use self::{
+    attention::AttentionScreen, character_card::CharacterCardScreen,
+    character_select::CharacterSelectScreen, choice::ChoiceScreen, reply::ReplyScreen,
+    splash::SplashScreen,
+};
+
+/// Width of the controller's display, in pixels.
+pub const WIDTH: u16 = 320;
+
+/// Height of the controller's display, in pixels.
+pub const HEIGHT: u16 = 240;
+
+/// The full area of the controller's display.
+pub fn screen_box() -> Rectangle {
+    Rectangle::new(Point::zero(), Size::new(WIDTH.into(), HEIGHT.into()))
+}
+
+/// Draws a screen in its initial state.
+pub fn draw_screen<D>(screen: &Screen, target: &mut D) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Rgb666>,
+{
+    match screen {
+        Screen::CharacterSelect(s) => CharacterSelectScreen::new(s.clone()).draw(target),
+        Screen::Choices(s) => ChoiceScreen::new(s.clone()).draw(target),
+        Screen::Reply(s) => ReplyScreen::new(s.clone()).draw(target),
+        Screen::Attention(s) => AttentionScreen::new(s.clone()).draw(target),
+    }
+}
+
+/// Describes how the content of a screen will fit on the display.
+pub fn report(screen: &Screen, screen_box: Rectangle) -> ScreenReport {
+    match screen {
+        Screen::CharacterSelect(_) | Screen::Attention(_) => ScreenReport::default(),
+        Screen::Choices(s) => ScreenReport {
+            text: ChoiceScreen::text_fit(s, screen_box).into_iter().collect(),
+        },
+        Screen::Reply(s) => ScreenReport {
+            text: ReplyScreen::text_fit(s, screen_box),
+        },
+    }
+}
+
+/// A screen with content that changes over time, such as scrolling or paged text.
+#[allow(clippy::large_enum_variant)]
+pub enum AnimatedScreen {
+    Choices(ChoiceScreen),
+    Reply(ReplyScreen),
+}
+
+impl AnimatedScreen {
+    /// Returns the animated form of a screen, if it has anything to animate.
+    pub fn new(screen: &Screen, screen_box: Rectangle) -> Option<Self> {
+        let screen = match screen {
+            Screen::CharacterSelect(_) | Screen::Attention(_) => return None,
+            Screen::Choices(s) => Self::Choices(ChoiceScreen::new(s.clone())),
+            Screen::Reply(s) => Self::Reply(ReplyScreen::new(s.clone())),
+        };
+
+        let animates = match &screen {
+            Self::Choices(s) => s.animates(screen_box),
+            Self::Reply(s) => s.animates(screen_box),
+        };
+
+        animates.then_some(screen)
+    }
+
+    pub fn step(&mut self) {
+        match self {
+            Self::Choices(s) => s.step(),
+            Self::Reply(s) => s.step(),
+        }
+    }
+}
+
+impl Drawable for AnimatedScreen {
+    type Color = Rgb666;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        match self {
+            Self::Choices(s) => s.draw(target),
+            Self::Reply(s) => s.draw(target),
+        }
+    }
+}
+
+/// Number of lines the text of a choice is wrapped onto, in the largest font, on a screen of the
+/// given size.
+pub fn choice_lines(text: &str, screen_box: Rectangle) -> u32 {
+    // Every box is the full width of the screen, so the number of choices does not matter
+    fitted_text::text_lines(text, choice_boxes(screen_box, 1)[0])
+}
+
+/// Splits the area into a box for each choice, stacked from top to bottom.
+fn choice_boxes(screen_box: Rectangle, count: usize) -> Vec<Rectangle, MAX_CHOICES> {
+    let count = count.clamp(1, MAX_CHOICES) as u32;
+    let option_height = screen_box.size.height / count;
+
+    (0..count)
+        .map(|i| {
+            let size = Size::new(screen_box.size.width, option_height);
+            let inner_size = size - Size::new(0, 4);
+
+            Rectangle::new(
+                screen_box.top_left + Point::new(0, (option_height * i) as i32),
+                size,
+            )
+            .resized(inner_size, AnchorPoint::Center)
+        })
+        .collect()
+}
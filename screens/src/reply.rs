@@ -0,0 +1,176 @@
+use super::{
+    choice::{choices_fit, draw_choices},
+    fitted_text::{measure_text_height, textbox_style},
+};
+use core::fmt::Write;
+use embedded_graphics::{
+    geometry::AnchorPoint,
+    mono_font::{
+        iso_8859_1::{FONT_6X10, FONT_9X18},
+        MonoTextStyle,
+    },
+    pixelcolor::Rgb666,
+    prelude::{DrawTarget, Point, Primitive, Size, Transform, WebColors},
+    primitives::{PrimitiveStyleBuilder, Rectangle},
+    text::{Alignment, Text},
+    Drawable,
+};
+use embedded_text::TextBox;
+use heapless::Vec;
+use icd::{TextFit, MAX_CHOICES};
+
+/// Height of the area the response is shown in, the choices share the rest of the screen.
+const RESPONSE_HEIGHT: u32 = 108;
+
+/// Number of animation steps each page of the response is shown for.
+pub(crate) const PAGE_STEPS: u32 = 4;
+
+fn layout(screen_box: Rectangle) -> (Rectangle, Rectangle) {
+    // Leaves a gap between the response and the choices, matching the gap between each choice
+    let response = screen_box.resized(
+        Size::new(screen_box.size.width, RESPONSE_HEIGHT - 4),
+        AnchorPoint::TopCenter,
+    );
+    let choices = screen_box.resized(
+        Size::new(
+            screen_box.size.width,
+            screen_box.size.height - RESPONSE_HEIGHT,
+        ),
+        AnchorPoint::BottomCenter,
+    );
+
+    (response, choices)
+}
+
+/// Area of the response box that text is drawn in, leaving room for the page number at the
+/// bottom.
+fn response_text_rect(rect: Rectangle) -> Rectangle {
+    rect.resized(rect.size - Size::new(8, 20), AnchorPoint::TopCenter)
+        .translate(Point::new(0, 4))
+}
+
+/// Pagination of the response text.
+struct Pages {
+    count: u32,
+    lines_per_page: u32,
+}
+
+impl Pages {
+    fn new(text: &str, rect: Rectangle) -> Self {
+        let rect = response_text_rect(rect);
+        let line_height = FONT_9X18.character_size.height;
+
+        let lines = measure_text_height(&FONT_9X18, text, rect.size.width) / line_height;
+        let lines_per_page = (rect.size.height / line_height).max(1);
+
+        Self {
+            count: lines.div_ceil(lines_per_page).max(1),
+            lines_per_page,
+        }
+    }
+
+    fn page(&self, step: u32) -> u32 {
+        (step / PAGE_STEPS) % self.count
+    }
+}
+
+pub struct ReplyScreen {
+    content: icd::ReplyScreen,
+    /// Number of animation steps taken, zero when first drawn
+    step: u32,
+}
+
+impl ReplyScreen {
+    pub fn new(content: icd::ReplyScreen) -> Self {
+        Self { content, step: 0 }
+    }
+
+    /// Reports how the response and each choice fit on a screen of the given size.
+    pub fn text_fit(
+        content: &icd::ReplyScreen,
+        screen_box: Rectangle,
+    ) -> Vec<TextFit, { MAX_CHOICES + 1 }> {
+        let (response_rect, choices_rect) = layout(screen_box);
+
+        let response_fit = if Pages::new(&content.response, response_rect).count > 1 {
+            TextFit::Paged
+        } else {
+            TextFit::Fits
+        };
+
+        core::iter::once(response_fit)
+            .chain(choices_fit(&content.choices, choices_rect))
+            .collect()
+    }
+
+    /// True if the response is paged or any choice text needs scrolling.
+    pub fn animates(&self, screen_box: Rectangle) -> bool {
+        Self::text_fit(&self.content, screen_box)
+            .iter()
+            .any(|fit| matches!(fit, TextFit::Scrolled | TextFit::Paged))
+    }
+
+    /// Advances the response page and scroll position of overflowing choice text, after which
+    /// only the parts of the screen that change are redrawn.
+    pub fn step(&mut self) {
+        self.step = self.step.wrapping_add(1).max(1);
+    }
+}
+
+impl Drawable for ReplyScreen {
+    type Color = Rgb666;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let (response_rect, choices_rect) = layout(target.bounding_box());
+        let pages = Pages::new(&self.content.response, response_rect);
+
+        if self.step == 0 {
+            target.clear(Rgb666::CSS_BLACK)?;
+        }
+
+        if self.step == 0 || (pages.count > 1 && self.step % PAGE_STEPS == 0) {
+            let choices = &self.content.choices;
+
+            response_rect
+                .into_styled(
+                    PrimitiveStyleBuilder::new()
+                        .stroke_width(2)
+                        .stroke_color(choices.margin_colour())
+                        .fill_color(choices.background_colour())
+                        .build(),
+                )
+                .draw(target)?;
+
+            let page = pages.page(self.step);
+            let line_height = FONT_9X18.character_size.height;
+
+            TextBox::with_textbox_style(
+                &self.content.response,
+                response_text_rect(response_rect),
+                MonoTextStyle::new(&FONT_9X18, choices.text_colour()),
+                textbox_style(),
+            )
+            .set_vertical_offset(-((page * pages.lines_per_page * line_height) as i32))
+            .draw(target)?;
+
+            if pages.count > 1 {
+                let mut label = heapless::String::<8>::new();
+                let _ = write!(label, "{}/{}", page + 1, pages.count);
+
+                Text::with_alignment(
+                    &label,
+                    response_rect.anchor_point(AnchorPoint::BottomRight) + Point::new(-4, -4),
+                    MonoTextStyle::new(&FONT_6X10, choices.text_colour()),
+                    Alignment::Right,
+                )
+                .draw(target)?;
+            }
+        }
+
+        draw_choices(target, &self.content.choices, choices_rect, self.step)
+    }
+}
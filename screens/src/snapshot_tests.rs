@@ -0,0 +1,191 @@
+//! Compares each screen layout against a reference image.
+//!
+//! After an intentional change to a layout, regenerate the reference images by running the tests
+//! with `UPDATE_SNAPSHOTS=1` and check the new images over before committing them.
+
+use embedded_graphics::{
+    pixelcolor::{Rgb666, Rgb888, RgbColor},
+    prelude::Size,
+    Drawable,
+};
+use embedded_graphics_simulator::{OutputSettings, SimulatorDisplay};
+use icd::{CharacterDetails, ChoiceScreen, Screen};
+use std::{format, path::PathBuf};
+
+fn character(name: &str, description: &str, background: Rgb888) -> CharacterDetails {
+    CharacterDetails::new(
+        Rgb666::WHITE,
+        Rgb888::new(background.r(), background.g(), background.b()).into(),
+        Rgb666::BLACK,
+        name.try_into().unwrap(),
+        description.try_into().unwrap(),
+    )
+}
+
+fn choices(choices: &[&str]) -> ChoiceScreen {
+    ChoiceScreen::new(
+        Rgb666::WHITE,
+        Rgb888::new(0, 64, 128).into(),
+        Rgb666::BLACK,
+        choices.iter().map(|c| (*c).try_into().unwrap()).collect(),
+    )
+}
+
+fn new_display() -> SimulatorDisplay<Rgb666> {
+    SimulatorDisplay::new(Size::new(crate::WIDTH.into(), crate::HEIGHT.into()))
+}
+
+fn reply(response: &str, choices_text: &[&str]) -> icd::ReplyScreen {
+    icd::ReplyScreen {
+        response: response.try_into().unwrap(),
+        choices: choices(choices_text),
+    }
+}
+
+fn assert_snapshot(name: &str, screen: &Screen) {
+    let mut display = new_display();
+    crate::draw_screen(screen, &mut display).unwrap();
+    assert_display_snapshot(name, &display);
+}
+
+fn assert_display_snapshot(name: &str, display: &SimulatorDisplay<Rgb666>) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("snapshots")
+        .join(format!("{name}.png"));
+
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        display
+            .to_rgb_output_image(&OutputSettings::default())
+            .save_png(&path)
+            .unwrap();
+        return;
+    }
+
+    let expected = SimulatorDisplay::<Rgb666>::load_png(&path)
+        .unwrap_or_else(|e| panic!("failed to load {}: {e}", path.display()));
+
+    if *display != expected {
+        let actual_path = path.with_extension("actual.png");
+        display
+            .to_rgb_output_image(&OutputSettings::default())
+            .save_png(&actual_path)
+            .unwrap();
+        panic!(
+            "{name} does not match {}, rendered output saved to {}",
+            path.display(),
+            actual_path.display()
+        );
+    }
+}
+
+#[test]
+fn character_select() {
+    assert_snapshot(
+        "character_select",
+        &Screen::CharacterSelect(icd::CharacterSelectScreen {
+            prev: character("Ember", "A retired dragon.", Rgb888::new(128, 32, 0)),
+            selected: character(
+                "Professor Quill",
+                "A forgetful inventor who is always one experiment away from a breakthrough, or \
+                 an explosion.",
+                Rgb888::new(32, 64, 32),
+            ),
+            next: character("Marlow", "A private detective.", Rgb888::new(16, 16, 64)),
+        }),
+    );
+}
+
+#[test]
+fn choices_that_fit() {
+    assert_snapshot(
+        "choices_that_fit",
+        &Screen::Choices(choices(&[
+            "Tell me about the dragon.",
+            "What is that noise?",
+            "Goodbye.",
+        ])),
+    );
+}
+
+#[test]
+fn choices_that_overflow() {
+    assert_snapshot(
+        "choices_that_overflow",
+        &Screen::Choices(choices(&[
+            "I have a question about the strange machine in the corner of your workshop, the one \
+             that keeps humming whenever you walk past it.",
+            "Surely the explosion last week was not your fault? Everyone says it was the fault of \
+             the apprentice, who had been told many times not to touch the big red lever, but \
+             who touched it anyway.",
+            "Okay.",
+        ])),
+    );
+}
+
+#[test]
+fn two_choices() {
+    assert_snapshot(
+        "two_choices",
+        &Screen::Choices(choices(&["Yes, press the button.", "No, leave it alone."])),
+    );
+}
+
+#[test]
+fn four_choices() {
+    assert_snapshot(
+        "four_choices",
+        &Screen::Choices(choices(&[
+            "Tell me about the dragon.",
+            "What is that noise?",
+            "Can I see the workshop?",
+            "Goodbye.",
+        ])),
+    );
+}
+
+#[test]
+fn reply_that_fits() {
+    assert_snapshot(
+        "reply",
+        &Screen::Reply(reply(
+            "Ah, a visitor! Mind the soot, I was just testing the new furnace.",
+            &["What are you making?", "Is it safe?", "Goodbye."],
+        )),
+    );
+}
+
+#[test]
+fn reply_over_several_pages() {
+    let content = reply(
+        "Ah, a visitor! Mind the soot, I was just testing the new furnace. It is meant to melt \
+         brass for the gears of my clockwork owl, but so far it has only melted two kettles, \
+         one boot and the better part of the workbench. The owl does not seem to mind. Would \
+         you like to see it? It can nearly fly now, and it only bites people it does not like.",
+        &["Yes, show me the owl.", "Does it bite often?", "Goodbye."],
+    );
+
+    assert_snapshot("reply_page_1", &Screen::Reply(content.clone()));
+
+    // Steps through the animation as the controller does, until the second page is shown
+    let mut display = new_display();
+    let mut screen = crate::ReplyScreen::new(content);
+    screen.draw(&mut display).unwrap();
+    for _ in 0..crate::reply::PAGE_STEPS {
+        screen.step();
+        screen.draw(&mut display).unwrap();
+    }
+    assert_display_snapshot("reply_page_2", &display);
+}
+
+#[test]
+fn attention() {
+    assert_snapshot(
+        "attention",
+        &Screen::Attention(icd::AttentionScreen {
+            title: "Printer needs attention".try_into().unwrap(),
+            message: "The printer is out of paper, load a new roll."
+                .try_into()
+                .unwrap(),
+        }),
+    );
+}
@@ -1,5 +1,5 @@
 use embedded_graphics::{
-    mono_font::{ascii::FONT_10X20, MonoTextStyle},
+    mono_font::{iso_8859_1::FONT_10X20, MonoTextStyle},
     pixelcolor::Rgb666,
     prelude::{DrawTarget, Primitive, WebColors},
     primitives::PrimitiveStyleBuilder,
@@ -7,7 +7,7 @@ use embedded_graphics::{
     Drawable,
 };
 
-pub(crate) struct SplashScreen {}
+pub struct SplashScreen {}
 
 impl Drawable for SplashScreen {
     type Color = Rgb666;